@@ -1,26 +1,633 @@
 use nih_plug_vizia::vizia::prelude::*;
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+use crate::migrate::{self, PRESET_VERSION};
+
+/// How far outside of the unit square a node may lie before it's considered
+/// invalid rather than just a rounding error.
+const RANGE_TOLERANCE: f32 = 1e-4;
+
+/// How far a segment at full tension bends. Its shape is then given by `t^n`
+/// or its inverse, with `n` this constant.
+const MAX_TENSION_EXPONENT: f32 = 8.0;
+
+/// How many points within a segment a mirrored bend is fitted to.
+const MIRROR_FIT_POINTS: usize = 32;
+
+/// The horizontal gap left between two nodes to make a curve jump from one
+/// value to another.
+pub(crate) const JUMP: f32 = 1e-3;
 
 /// A node in a curve.
-#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[derive(Clone, Copy, Default, Data, Serialize, Deserialize)]
 pub struct Node {
     pub x: f32,
     pub y: f32,
+
+    /// How much the segment starting at this node bends, from -1 to 1. Positive
+    /// values make it start slowly and rush towards the next node, negative
+    /// values do the opposite, and zero leaves it as is.
+    #[serde(default)]
+    pub tension: f32,
+}
+
+impl Node {
+    /// A node with no tension.
+    pub const fn new(x: f32, y: f32) -> Self {
+        Self { x, y, tension: 0.0 }
+    }
+
+    /// Bends the relative position `t` within the segment starting at this
+    /// node according to its tension.
+    fn bend(self, t: f32) -> f32 {
+        let tension = self.tension.clamp(-1.0, 1.0);
+        if tension == 0.0 || !tension.is_finite() {
+            t
+        } else {
+            t.powf(MAX_TENSION_EXPONENT.powf(tension))
+        }
+    }
+
+    /// The tension that bends a segment played back to front closest to how
+    /// this node bends it. The mirror of `t^n` is `1 - (1 - t)^n`, which no
+    /// tension matches exactly, so this is the one that strays the least from
+    /// it at any of a handful of points.
+    fn mirrored_tension(self) -> f32 {
+        if self.tension == 0.0 {
+            return 0.0;
+        }
+
+        let error = |tension: f32| {
+            let node = Node { tension, ..self };
+            (1..MIRROR_FIT_POINTS)
+                .map(|i| {
+                    let t = i as f32 / MIRROR_FIT_POINTS as f32;
+                    (node.bend(t) - (1.0 - self.bend(1.0 - t))).abs()
+                })
+                .fold(0.0, f32::max)
+        };
+
+        // The mirror bends the other way, and the error only has the one
+        // minimum on that side, so narrow down the range around it.
+        let (mut low, mut high) = if self.tension > 0.0 {
+            (-1.0, 0.0)
+        } else {
+            (0.0, 1.0)
+        };
+        for _ in 0..32 {
+            let third = (high - low) / 3.0;
+            if error(low + third) < error(high - third) {
+                high -= third;
+            } else {
+                low += third;
+            }
+        }
+
+        (low + high) / 2.0
+    }
+}
+
+/// How the value of a [`Curve`] is computed in between its nodes.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Data, Serialize, Deserialize)]
+pub enum Interpolation {
+    /// Holds the value of each node until the next one.
+    Drop,
+
+    /// Draws straight lines between nodes.
+    #[default]
+    Linear,
+
+    /// Eases in and out of every node.
+    Cubic,
+
+    /// Draws a smooth spline through all nodes.
+    Hermite,
+}
+
+impl Interpolation {
+    /// Interpolates between nodes `a` and `b`, where `t` is the relative
+    /// position between them after bending it by the tension of `a`. The
+    /// Hermite spline also takes into account the nodes right before and after,
+    /// if any.
+    ///
+    /// The result is clamped to the unit interval in every mode. Only the
+    /// Hermite spline can overshoot its nodes, but clamping them all keeps
+    /// every mode to the same guarantee.
+    fn interpolate(
+        self,
+        before: Option<Node>,
+        a: Node,
+        b: Node,
+        after: Option<Node>,
+        t: f32,
+    ) -> f32 {
+        let t = a.bend(t);
+
+        let value = match self {
+            Self::Drop => a.y,
+            Self::Linear => a.y + (b.y - a.y) * t,
+            Self::Cubic => a.y + (b.y - a.y) * t * t * (3.0 - 2.0 * t),
+            Self::Hermite => {
+                // Finite difference slopes, scaled to the segment.
+                let width = b.x - a.x;
+                let slope = |p: Node, q: Node| {
+                    if q.x > p.x {
+                        (q.y - p.y) / (q.x - p.x) * width
+                    } else {
+                        0.0
+                    }
+                };
+                let m_a = before.map_or(b.y - a.y, |before| slope(before, b));
+                let m_b = after.map_or(b.y - a.y, |after| slope(a, after));
+
+                let t2 = t * t;
+                let t3 = t2 * t;
+                (2.0 * t3 - 3.0 * t2 + 1.0) * a.y
+                    + (t3 - 2.0 * t2 + t) * m_a
+                    + (-2.0 * t3 + 3.0 * t2) * b.y
+                    + (t3 - t2) * m_b
+            }
+        };
+        value.clamp(0.0, 1.0)
+    }
+}
+
+/// A reason why a list of nodes doesn't make up a valid [`Curve`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CurveError {
+    /// There are no nodes.
+    Empty,
+
+    /// A node has a coordinate or tension that is infinite or NaN.
+    NotFinite {
+        /// The index of the node.
+        index: usize,
+    },
+
+    /// A node lies outside of the unit square.
+    OutOfRange {
+        /// The index of the node.
+        index: usize,
+    },
+
+    /// Two nodes share the same `x` coordinate.
+    Overlapping {
+        /// The shared coordinate.
+        x: f32,
+    },
+}
+
+impl fmt::Display for CurveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "the curve has no nodes"),
+            Self::NotFinite { index } => write!(f, "node {index} is not a finite number"),
+            Self::OutOfRange { index } => write!(f, "node {index} lies outside of the unit square"),
+            Self::Overlapping { x } => write!(f, "more than one node at x = {x}"),
+        }
+    }
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+impl std::error::Error for CurveError {}
+
+/// A [`Curve`] as it's read from a file, before it's validated.
+#[derive(Deserialize)]
+struct UncheckedCurve {
+    nodes: Vec<Node>,
+
+    #[serde(default)]
+    interpolation: Interpolation,
+}
+
+impl TryFrom<UncheckedCurve> for Curve {
+    type Error = CurveError;
+
+    fn try_from(curve: UncheckedCurve) -> Result<Self, CurveError> {
+        Self::new(curve.nodes, curve.interpolation)
+    }
+}
+
+/// A function from the unit interval to itself, given by a list of nodes.
+///
+/// A valid curve has its nodes sorted by strictly increasing `x` coordinates,
+/// all of them within the unit square, starting at `x = 0` and ending at
+/// `x = 1`. Curves built through [`Curve::new`] or deserialized always are.
+#[derive(Clone, Data, Serialize, Deserialize)]
+#[serde(try_from = "UncheckedCurve")]
 pub struct Curve {
-    pub nodes: Vec<Node>,
+    /// The nodes of the curve. These are kept private so that the curve can
+    /// only ever be changed into another valid one.
+    nodes: Vec<Node>,
+
+    /// How the curve is drawn between nodes.
+    #[serde(default)]
+    pub interpolation: Interpolation,
 }
 
 impl Curve {
+    /// Builds a valid curve from a list of nodes. See [`Self::normalize`].
+    pub fn new(nodes: Vec<Node>, interpolation: Interpolation) -> Result<Self, CurveError> {
+        let mut curve = Self {
+            nodes,
+            interpolation,
+        };
+        curve.normalize()?;
+        Ok(curve)
+    }
+
+    /// Makes the curve valid, or returns why that can't be done.
+    ///
+    /// Nodes are sorted, coordinates that are only slightly out of range are
+    /// clamped, tensions are clamped, and nodes at `x = 0` and `x = 1` are added
+    /// if missing, continuing the curve flatly. On an error the curve may be
+    /// left partially normalized.
+    pub fn normalize(&mut self) -> Result<(), CurveError> {
+        if self.nodes.is_empty() {
+            return Err(CurveError::Empty);
+        }
+
+        let range = -RANGE_TOLERANCE..=1.0 + RANGE_TOLERANCE;
+        for (index, node) in self.nodes.iter_mut().enumerate() {
+            if !(node.x.is_finite() && node.y.is_finite() && node.tension.is_finite()) {
+                return Err(CurveError::NotFinite { index });
+            }
+
+            if !(range.contains(&node.x) && range.contains(&node.y)) {
+                return Err(CurveError::OutOfRange { index });
+            }
+
+            node.x = node.x.clamp(0.0, 1.0);
+            node.y = node.y.clamp(0.0, 1.0);
+            node.tension = node.tension.clamp(-1.0, 1.0);
+        }
+
+        self.nodes.sort_by(|a, b| a.x.total_cmp(&b.x));
+        if let Some(pair) = self.nodes.windows(2).find(|pair| pair[0].x == pair[1].x) {
+            return Err(CurveError::Overlapping { x: pair[0].x });
+        }
+
+        let first = self.nodes[0];
+        if first.x > 0.0 {
+            self.nodes.insert(0, Node::new(0.0, first.y));
+        }
+
+        let last = self.nodes[self.nodes.len() - 1];
+        if last.x < 1.0 {
+            self.nodes.push(Node::new(1.0, last.y));
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the nodes within the span of `nodes` by them, and returns the
+    /// resulting curve if it's valid. The curve stays as is outside of that
+    /// span.
+    pub fn splice(&self, nodes: &[Node]) -> Result<Self, CurveError> {
+        if nodes.is_empty() {
+            return Err(CurveError::Empty);
+        }
+
+        let start = nodes
+            .iter()
+            .map(|node| node.x)
+            .fold(f32::INFINITY, f32::min);
+        let end = nodes
+            .iter()
+            .map(|node| node.x)
+            .fold(f32::NEG_INFINITY, f32::max);
+
+        let mut spliced: Vec<Node> = self
+            .nodes
+            .iter()
+            .filter(|node| node.x < start || node.x > end)
+            .copied()
+            .collect();
+        spliced.extend_from_slice(nodes);
+
+        Self::new(spliced, self.interpolation)
+    }
+
+    /// The nodes of the curve, sorted from left to right.
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
     pub fn iter(&self) -> std::slice::Iter<Node> {
         self.nodes.iter()
     }
+
+    /// Whether the curve has no nodes.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// The index of the node starting the segment that contains `x`, or `None`
+    /// if `x` comes before every node.
+    fn segment(&self, x: f32) -> Option<usize> {
+        self.nodes
+            .partition_point(|node| node.x <= x)
+            .checked_sub(1)
+    }
+
+    /// Evaluates a given segment of the curve at `x`.
+    fn segment_value(&self, idx: usize, x: f32) -> f32 {
+        let a = self.nodes[idx];
+        let Some(&b) = self.nodes.get(idx + 1) else {
+            return a.y;
+        };
+
+        let t = if b.x > a.x {
+            ((x - a.x) / (b.x - a.x)).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+
+        let before = idx.checked_sub(1).map(|i| self.nodes[i]);
+        let after = self.nodes.get(idx + 2).copied();
+        self.interpolation.interpolate(before, a, b, after, t)
+    }
+
+    /// Evaluates the curve at `x`. The curve is flat before its first node and
+    /// after its last, and an empty curve is zero everywhere. The value always
+    /// lies within the unit interval, whatever the interpolation.
+    pub fn value_at(&self, x: f32) -> f32 {
+        match self.segment(x) {
+            Some(idx) => self.segment_value(idx, x),
+            None => self.nodes.first().map_or(0.0, |node| node.y),
+        }
+    }
+
+    /// Replaces every position in a block by the value of the curve there.
+    ///
+    /// This gives the same results as [`Self::value_at`], but is cheaper when
+    /// consecutive positions tend to lie on the same segment, as they do on the
+    /// audio thread.
+    pub fn fill_values(&self, block: &mut [f32]) {
+        let mut current: Option<usize> = None;
+
+        for x in block {
+            let in_segment = current.map_or(false, |idx| {
+                self.nodes[idx].x <= *x && self.nodes.get(idx + 1).map_or(true, |b| *x < b.x)
+            });
+
+            if !in_segment {
+                current = self.segment(*x);
+            }
+
+            *x = match current {
+                Some(idx) => self.segment_value(idx, *x),
+                None => self.nodes.first().map_or(0.0, |node| node.y),
+            };
+        }
+    }
+}
+
+/// Transformations that build a new curve out of an existing one.
+impl Curve {
+    /// Mirrors the curve horizontally, so that it plays back to front. Bent
+    /// segments are only mirrored approximately, see
+    /// [`Node::mirrored_tension`].
+    pub fn flip(&self) -> Self {
+        let last = self.nodes.len().saturating_sub(1);
+        let nodes = (0..=last)
+            .rev()
+            .map(|idx| {
+                let node = self.nodes[idx];
+                let x = 1.0 - node.x;
+
+                // A held value belongs to the node before, and a segment that
+                // started slowly now ends slowly.
+                match (self.interpolation, idx.checked_sub(1)) {
+                    (Interpolation::Drop, Some(before)) => Node::new(x, self.nodes[before].y),
+                    (_, Some(before)) => Node {
+                        x,
+                        y: node.y,
+                        tension: self.nodes[before].mirrored_tension(),
+                    },
+                    (_, None) => Node::new(x, node.y),
+                }
+            })
+            .collect();
+
+        Self {
+            nodes,
+            interpolation: self.interpolation,
+        }
+    }
+
+    /// Mirrors the curve vertically.
+    pub fn invert(&self) -> Self {
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|node| Node {
+                y: 1.0 - node.y,
+                ..*node
+            })
+            .collect();
+
+        Self {
+            nodes,
+            interpolation: self.interpolation,
+        }
+    }
+
+    /// Moves the curve to the right by `amount`, wrapping around the end. A
+    /// negative amount moves it to the left.
+    pub fn shift(&self, amount: f32) -> Result<Self, CurveError> {
+        let amount = amount.rem_euclid(1.0);
+        if !(JUMP..=1.0 - JUMP).contains(&amount) {
+            return Ok(self.clone());
+        }
+
+        // The part from `split` onwards ends up at the start. A node right at
+        // the split keeps bending the segment it starts.
+        let split = 1.0 - amount;
+        let tension = self
+            .nodes
+            .iter()
+            .find(|node| (node.x - split).abs() < JUMP)
+            .map_or(0.0, |node| node.tension);
+        let mut nodes = vec![Node {
+            x: 0.0,
+            y: self.value_at(split),
+            tension,
+        }];
+        nodes.extend(
+            self.nodes
+                .iter()
+                .filter(|node| node.x - split >= JUMP && node.x - split < amount - JUMP)
+                .map(|node| Node {
+                    x: node.x - split,
+                    ..*node
+                }),
+        );
+        nodes.push(Node::new(amount - JUMP, self.value_at(1.0)));
+        nodes.extend(
+            self.nodes
+                .iter()
+                .filter(|node| node.x < split)
+                .map(|node| Node {
+                    x: node.x + amount,
+                    ..*node
+                }),
+        );
+        nodes.push(Node::new(1.0, self.value_at(split)));
+
+        Self::new(nodes, self.interpolation)
+    }
+
+    /// Scales every value by `factor`, towards or away from `pivot`. Values
+    /// that end up outside of the unit interval are clamped.
+    pub fn scale_y(&self, factor: f32, pivot: f32) -> Self {
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|node| Node {
+                y: (pivot + (node.y - pivot) * factor).clamp(0.0, 1.0),
+                ..*node
+            })
+            .collect();
+
+        Self {
+            nodes,
+            interpolation: self.interpolation,
+        }
+    }
+
+    /// Squeezes the curve to fit `times` times into the bar. Beat curves have
+    /// their values squeezed as well, so that every copy plays its own part of
+    /// the bar the way the whole curve plays the whole bar.
+    pub fn repeat(&self, times: usize, mode: Mode) -> Result<Self, CurveError> {
+        if times <= 1 {
+            return Ok(self.clone());
+        }
+
+        let width = 1.0 / times as f32;
+        let mut nodes = Vec::with_capacity(self.nodes.len() * times);
+        for copy in 0..times {
+            let start = copy as f32 * width;
+            let end = start + width;
+            let is_last = copy + 1 == times;
+
+            for node in &self.nodes {
+                let mut x = start + node.x * width;
+                if !is_last && x > end - JUMP {
+                    // Make way for the start of the next copy.
+                    if node.x < 1.0 {
+                        continue;
+                    }
+                    x = end - JUMP;
+                }
+
+                let y = match mode {
+                    Mode::Beat => start + node.y * width,
+                    Mode::Vol => node.y,
+                };
+                nodes.push(Node { x, y, ..*node });
+            }
+        }
+
+        Self::new(nodes, self.interpolation)
+    }
+
+    /// Moves every node to the nearest multiple of `step`. Where more than one
+    /// node ends up in the same spot, only the one that was closest to it is
+    /// kept.
+    pub fn quantize(&self, step: f32) -> Result<Self, CurveError> {
+        if step.is_nan() || step <= 0.0 {
+            return Ok(self.clone());
+        }
+
+        let mut snapped: Vec<(f32, Node)> = self
+            .nodes
+            .iter()
+            .map(|node| {
+                let x = ((node.x / step).round() * step).min(1.0);
+                ((x - node.x).abs(), Node { x, ..*node })
+            })
+            .collect();
+
+        snapped
+            .sort_by(|(a_dist, a), (b_dist, b)| a.x.total_cmp(&b.x).then(a_dist.total_cmp(b_dist)));
+        snapped.dedup_by(|(_, b), (_, a)| a.x == b.x);
+
+        Self::new(
+            snapped.into_iter().map(|(_, node)| node).collect(),
+            self.interpolation,
+        )
+    }
+
+    /// Pulls every node between the endpoints towards the average of its
+    /// neighbours, by an `amount` from 0 to 1.
+    pub fn smooth(&self, amount: f32) -> Self {
+        let amount = amount.clamp(0.0, 1.0);
+        let mut nodes = self.nodes.clone();
+        for idx in 1..nodes.len().saturating_sub(1) {
+            let average = (self.nodes[idx - 1].y + self.nodes[idx + 1].y) / 2.0;
+            nodes[idx].y += (average - nodes[idx].y) * amount;
+        }
+
+        Self {
+            nodes,
+            interpolation: self.interpolation,
+        }
+    }
+
+    /// Removes the nodes that lie within `tolerance` of the straight line
+    /// between the nodes that are kept, using the Ramer-Douglas-Peucker
+    /// algorithm. The endpoints are always kept.
+    pub fn simplify(&self, tolerance: f32) -> Self {
+        let mut keep = vec![false; self.nodes.len()];
+        if let (Some(first), Some(last)) = (keep.first_mut(), self.nodes.len().checked_sub(1)) {
+            *first = true;
+            keep[last] = true;
+            self.mark_kept(0, last, tolerance, &mut keep);
+        }
+
+        let nodes = self
+            .nodes
+            .iter()
+            .zip(keep)
+            .filter(|(_, keep)| *keep)
+            .map(|(node, _)| *node)
+            .collect();
+
+        Self {
+            nodes,
+            interpolation: self.interpolation,
+        }
+    }
+
+    /// Marks the nodes between `start` and `end` that [`Self::simplify`] keeps.
+    fn mark_kept(&self, start: usize, end: usize, tolerance: f32, keep: &mut [bool]) {
+        let (a, b) = (self.nodes[start], self.nodes[end]);
+        let distance = |node: &Node| {
+            let t = if b.x > a.x {
+                (node.x - a.x) / (b.x - a.x)
+            } else {
+                0.0
+            };
+            (node.y - (a.y + (b.y - a.y) * t)).abs()
+        };
+
+        let farthest = (start + 1..end)
+            .map(|idx| (idx, distance(&self.nodes[idx])))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        if let Some((idx, distance)) = farthest {
+            if distance > tolerance {
+                keep[idx] = true;
+                self.mark_kept(start, idx, tolerance, keep);
+                self.mark_kept(idx, end, tolerance, keep);
+            }
+        }
+    }
 }
 
 /// Whether a preset is meant for a beat or a volume.
-#[derive(Clone, Copy, Serialize, Deserialize)]
+#[derive(Clone, Copy, PartialEq, Eq, Data, Serialize, Deserialize)]
 pub enum Mode {
     /// A beat or timestretching preset.
     Beat,
@@ -29,18 +636,177 @@ pub enum Mode {
     Vol,
 }
 
+/// The note value a [`Length`] is counted in.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Data, Serialize, Deserialize)]
+pub enum LengthUnit {
+    /// Bars, in whatever time signature the host is in.
+    #[default]
+    Bars,
+
+    /// Quarter notes.
+    Beats,
+
+    /// Eighth notes.
+    Eighths,
+
+    /// Sixteenth notes.
+    Sixteenths,
+
+    /// Quarter note triplets, three to a half note.
+    QuarterTriplets,
+
+    /// Eighth note triplets, three to a quarter note.
+    EighthTriplets,
+}
+
+impl LengthUnit {
+    /// All units, in the order they're offered in the editor.
+    pub const ALL: [Self; 6] = [
+        Self::Bars,
+        Self::Beats,
+        Self::Eighths,
+        Self::Sixteenths,
+        Self::QuarterTriplets,
+        Self::EighthTriplets,
+    ];
+
+    /// The length of the unit in quarter notes, given the length of a bar.
+    pub fn beats(self, bar_beats: f64) -> f64 {
+        match self {
+            Self::Bars => bar_beats,
+            Self::Beats => 1.0,
+            Self::Eighths => 0.5,
+            Self::Sixteenths => 0.25,
+            Self::QuarterTriplets => 2.0 / 3.0,
+            Self::EighthTriplets => 1.0 / 3.0,
+        }
+    }
+
+    /// A short name for the unit.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Bars => "bar",
+            Self::Beats => "beat",
+            Self::Eighths => "8th",
+            Self::Sixteenths => "16th",
+            Self::QuarterTriplets => "4T",
+            Self::EighthTriplets => "8T",
+        }
+    }
+}
+
+/// How long a [`Curve`] takes to play through once, from `x = 0` to `x = 1`.
+#[derive(Clone, Copy, PartialEq, Eq, Data, Serialize, Deserialize)]
+pub struct Length {
+    /// How many units the curve lasts. This is at least one.
+    pub count: u32,
+
+    /// The note value the length is counted in.
+    pub unit: LengthUnit,
+}
+
+impl Length {
+    /// The longest a curve may be, in units.
+    pub const MAX_COUNT: u32 = 64;
+
+    /// The length in quarter notes, given the length of a bar.
+    pub fn beats(self, bar_beats: f64) -> f64 {
+        self.count.clamp(1, Self::MAX_COUNT) as f64 * self.unit.beats(bar_beats)
+    }
+
+    /// The longest length in the same unit that's no longer than this one nor
+    /// than `max_beats` quarter notes, given the length of a bar. This is never
+    /// shorter than a single unit.
+    pub fn fit(self, bar_beats: f64, max_beats: f64) -> Self {
+        let max_count = (max_beats / self.unit.beats(bar_beats)).floor();
+        let max_count = if max_count >= 1.0 {
+            max_count.min(Self::MAX_COUNT as f64) as u32
+        } else {
+            1
+        };
+
+        Self {
+            count: self.count.clamp(1, max_count),
+            unit: self.unit,
+        }
+    }
+}
+
+impl Default for Length {
+    /// A single bar, which is how long every curve was before lengths could be
+    /// set.
+    fn default() -> Self {
+        Self {
+            count: 1,
+            unit: LengthUnit::Bars,
+        }
+    }
+}
+
+impl fmt::Display for Length {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.count, self.unit.name())?;
+        if self.count != 1 && matches!(self.unit, LengthUnit::Bars | LengthUnit::Beats) {
+            write!(f, "s")?;
+        }
+        Ok(())
+    }
+}
+
 /// A Functor preset. This consists of a [`Curve`] and the name of the preset.
-#[derive(Clone, Lens)]
+///
+/// Presets are serialized along with the version of their schema, and older
+/// versions are upgraded on load. See the [`migrate`] module.
+#[derive(Clone, Data, Lens)]
 pub struct Preset {
     /// The name of the preset.
     pub name: String,
 
     /// The curve the beat or volume follows.
     pub curve: Curve,
+
+    /// How long the curve lasts.
+    pub length: Length,
+}
+
+/// A [`Preset`] as it's serialized in the current version of the schema.
+#[derive(Serialize, Deserialize)]
+struct PresetRepr<N, C, L> {
+    version: u64,
+    name: N,
+    curve: C,
+    length: L,
+}
+
+impl Serialize for Preset {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        PresetRepr {
+            version: PRESET_VERSION,
+            name: &self.name,
+            curve: &self.curve,
+            length: &self.length,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Preset {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut value = serde_json::Value::deserialize(deserializer)?;
+        migrate::migrate_preset(&mut value).map_err(de::Error::custom)?;
+
+        let repr: PresetRepr<String, Curve, Length> =
+            serde_json::from_value(value).map_err(de::Error::custom)?;
+        Ok(Self {
+            name: repr.name,
+            curve: repr.curve,
+            length: repr.length,
+        })
+    }
 }
 
-/*
 /// A [`Preset`] bundled with the [`Mode`] it's intended for.
+#[derive(Clone, Data, Serialize, Deserialize)]
 pub struct ModePreset {
     /// The bundled preset.
     pub preset: Preset,
@@ -48,4 +814,306 @@ pub struct ModePreset {
     /// The intended mode for the preset.
     pub mode: Mode,
 }
-*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// How far apart two values may be to count as equal.
+    const EPSILON: f32 = 1e-5;
+
+    /// A curve through the given points, which must be valid.
+    fn curve(points: &[(f32, f32)], interpolation: Interpolation) -> Curve {
+        let nodes = points.iter().map(|&(x, y)| Node::new(x, y)).collect();
+        Curve::new(nodes, interpolation).expect("the curve should be valid")
+    }
+
+    /// The coordinates of the nodes of a curve.
+    fn points(curve: &Curve) -> Vec<(f32, f32)> {
+        curve.iter().map(|node| (node.x, node.y)).collect()
+    }
+
+    fn assert_points(curve: &Curve, expected: &[(f32, f32)]) {
+        let actual = points(curve);
+        let matches = actual.len() == expected.len()
+            && actual
+                .iter()
+                .zip(expected)
+                .all(|(a, b)| (a.0 - b.0).abs() < EPSILON && (a.1 - b.1).abs() < EPSILON);
+        assert!(matches, "expected {expected:?}, got {actual:?}");
+    }
+
+    fn assert_value(curve: &Curve, x: f32, expected: f32) {
+        assert_value_within(curve, x, expected, EPSILON);
+    }
+
+    fn assert_value_within(curve: &Curve, x: f32, expected: f32, tolerance: f32) {
+        let actual = curve.value_at(x);
+        assert!(
+            (actual - expected).abs() < tolerance,
+            "expected {expected} at {x}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn new_sorts_and_adds_endpoints() {
+        let curve = curve(&[(0.7, 0.2), (0.3, 0.8)], Interpolation::Linear);
+        assert_points(&curve, &[(0.0, 0.8), (0.3, 0.8), (0.7, 0.2), (1.0, 0.2)]);
+    }
+
+    #[test]
+    fn new_clamps_rounding_errors_and_tension() {
+        let nodes = vec![
+            Node {
+                x: -5e-5,
+                y: 1.0 + 5e-5,
+                tension: 3.0,
+            },
+            Node::new(1.0, 0.0),
+        ];
+        let curve = Curve::new(nodes, Interpolation::Linear).unwrap();
+        assert_points(&curve, &[(0.0, 1.0), (1.0, 0.0)]);
+        assert_eq!(curve.nodes()[0].tension, 1.0);
+    }
+
+    #[test]
+    fn new_rejects_invalid_nodes() {
+        let new = |nodes: Vec<Node>| Curve::new(nodes, Interpolation::Linear).err();
+
+        assert_eq!(new(Vec::new()), Some(CurveError::Empty));
+        assert_eq!(
+            new(vec![Node::new(0.0, 0.0), Node::new(f32::NAN, 0.5)]),
+            Some(CurveError::NotFinite { index: 1 })
+        );
+        assert_eq!(
+            new(vec![Node::new(0.5, 1.5)]),
+            Some(CurveError::OutOfRange { index: 0 })
+        );
+        assert_eq!(
+            new(vec![Node::new(0.5, 0.0), Node::new(0.5, 1.0)]),
+            Some(CurveError::Overlapping { x: 0.5 })
+        );
+    }
+
+    #[test]
+    fn normalize_reports_errors_on_changed_nodes() {
+        let mut curve = curve(&[(0.0, 0.0), (1.0, 1.0)], Interpolation::Linear);
+        assert!(curve.normalize().is_ok());
+
+        curve.nodes.push(Node::new(1.0, 0.5));
+        assert_eq!(curve.normalize(), Err(CurveError::Overlapping { x: 1.0 }));
+    }
+
+    #[test]
+    fn one_node_is_flat() {
+        let curve = curve(&[(0.4, 0.6)], Interpolation::Hermite);
+        assert_points(&curve, &[(0.0, 0.6), (0.4, 0.6), (1.0, 0.6)]);
+        for x in [0.0, 0.2, 0.4, 0.7, 1.0] {
+            assert_value(&curve, x, 0.6);
+        }
+    }
+
+    #[test]
+    fn empty_is_zero() {
+        let curve = Curve {
+            nodes: Vec::new(),
+            interpolation: Interpolation::Linear,
+        };
+        assert!(curve.is_empty());
+        assert_value(&curve, 0.5, 0.0);
+    }
+
+    #[test]
+    fn value_at_every_interpolation() {
+        let points = [(0.0, 0.0), (0.5, 1.0), (1.0, 0.0)];
+        let expected = [
+            (Interpolation::Drop, [0.0, 0.0, 1.0]),
+            (Interpolation::Linear, [0.2, 0.5, 0.5]),
+            (Interpolation::Cubic, [0.104, 0.5, 0.5]),
+            (Interpolation::Hermite, [0.232, 0.625, 0.625]),
+        ];
+
+        for (interpolation, values) in expected {
+            let curve = curve(&points, interpolation);
+            for (x, value) in [0.1, 0.25, 0.75].into_iter().zip(values) {
+                assert_value(&curve, x, value);
+            }
+
+            // Every mode goes through its nodes.
+            for (x, y) in points {
+                assert_value(&curve, x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn value_at_bends_with_tension() {
+        let nodes = vec![
+            Node {
+                x: 0.0,
+                y: 0.0,
+                tension: 1.0,
+            },
+            Node::new(1.0, 1.0),
+        ];
+        let curve = Curve::new(nodes, Interpolation::Linear).unwrap();
+        assert_value(&curve, 0.5, 0.5f32.powf(MAX_TENSION_EXPONENT));
+    }
+
+    #[test]
+    fn value_at_stays_in_range() {
+        // A Hermite spline through these overshoots on both sides.
+        let points = [(0.0, 0.0), (0.1, 1.0), (0.2, 1.0), (0.3, 0.0), (1.0, 0.0)];
+        for interpolation in [
+            Interpolation::Drop,
+            Interpolation::Linear,
+            Interpolation::Cubic,
+            Interpolation::Hermite,
+        ] {
+            let curve = curve(&points, interpolation);
+            for i in 0..=1000 {
+                let value = curve.value_at(i as f32 / 1000.0);
+                assert!((0.0..=1.0).contains(&value));
+            }
+        }
+    }
+
+    #[test]
+    fn fill_values_matches_value_at() {
+        let curve = curve(
+            &[(0.0, 0.1), (0.3, 0.9), (0.6, 0.4)],
+            Interpolation::Hermite,
+        );
+        let positions: Vec<f32> = (0..200).map(|i| (i as f32 * 0.037) % 1.0).collect();
+
+        let mut values = positions.clone();
+        curve.fill_values(&mut values);
+        for (x, value) in positions.into_iter().zip(values) {
+            assert_value(&curve, x, value);
+        }
+    }
+
+    #[test]
+    fn splice_replaces_the_span() {
+        let curve = curve(&[(0.0, 0.0), (0.5, 0.9), (1.0, 1.0)], Interpolation::Linear);
+        let spliced = curve
+            .splice(&[Node::new(0.4, 0.2), Node::new(0.6, 0.2)])
+            .unwrap();
+        assert_points(&spliced, &[(0.0, 0.0), (0.4, 0.2), (0.6, 0.2), (1.0, 1.0)]);
+
+        assert_eq!(curve.splice(&[]).err(), Some(CurveError::Empty));
+    }
+
+    #[test]
+    fn flip_and_invert() {
+        let curve = curve(
+            &[(0.0, 0.0), (0.25, 1.0), (1.0, 0.5)],
+            Interpolation::Linear,
+        );
+        assert_points(&curve.flip(), &[(0.0, 0.5), (0.75, 1.0), (1.0, 0.0)]);
+        assert_points(&curve.invert(), &[(0.0, 1.0), (0.25, 0.0), (1.0, 0.5)]);
+    }
+
+    #[test]
+    fn flip_mirrors_bent_segments() {
+        for tension in [-0.5, -0.25, 0.0, 0.25, 0.5] {
+            let curve = Curve::new(
+                vec![
+                    Node {
+                        tension,
+                        ..Node::new(0.0, 0.0)
+                    },
+                    Node::new(1.0, 1.0),
+                ],
+                Interpolation::Linear,
+            )
+            .unwrap();
+            let flipped = curve.flip();
+
+            for i in 0..=32 {
+                let x = i as f32 / 32.0;
+                assert_value_within(&flipped, x, curve.value_at(1.0 - x), 0.2);
+            }
+        }
+    }
+
+    #[test]
+    fn shift_wraps_around() {
+        let ramp = curve(&[(0.0, 0.0), (1.0, 1.0)], Interpolation::Linear);
+        // The jump at the seam takes up a little room.
+        let shifted = ramp.shift(0.5).unwrap();
+        assert_value_within(&shifted, 0.25, 0.75, JUMP);
+        assert_value(&shifted, 0.75, 0.25);
+
+        assert_points(&ramp.shift(1.0).unwrap(), &points(&ramp));
+    }
+
+    #[test]
+    fn shift_keeps_the_bend_at_the_split() {
+        let curve = Curve::new(
+            vec![
+                Node::new(0.0, 0.0),
+                Node {
+                    tension: 0.5,
+                    ..Node::new(0.5, 0.5)
+                },
+                Node::new(1.0, 1.0),
+            ],
+            Interpolation::Linear,
+        )
+        .unwrap();
+        // The jump at the seam squeezes the segment a little.
+        let shifted = curve.shift(0.5).unwrap();
+        for i in 0..=16 {
+            let x = i as f32 / 32.0 * (0.5 - JUMP);
+            let expected = curve.value_at(0.5 + x * 0.5 / (0.5 - JUMP));
+            assert_value_within(&shifted, x, expected, EPSILON);
+        }
+    }
+
+    #[test]
+    fn scale_y_clamps() {
+        let curve = curve(&[(0.0, 0.25), (1.0, 0.75)], Interpolation::Linear);
+        assert_points(&curve.scale_y(2.0, 0.5), &[(0.0, 0.0), (1.0, 1.0)]);
+        assert_points(&curve.scale_y(0.5, 0.5), &[(0.0, 0.375), (1.0, 0.625)]);
+    }
+
+    #[test]
+    fn repeat_squeezes_beats_but_not_volumes() {
+        let ramp = curve(&[(0.0, 0.0), (1.0, 1.0)], Interpolation::Linear);
+
+        // The jump between copies takes up a little room.
+        let beat = ramp.repeat(2, Mode::Beat).unwrap();
+        assert_value_within(&beat, 0.25, 0.25, JUMP);
+        assert_value(&beat, 0.75, 0.75);
+
+        let vol = ramp.repeat(2, Mode::Vol).unwrap();
+        assert_value_within(&vol, 0.25, 0.5, 2.0 * JUMP);
+        assert_value(&vol, 0.75, 0.5);
+    }
+
+    #[test]
+    fn quantize_keeps_the_closest_node() {
+        let curve = curve(
+            &[(0.0, 0.0), (0.3, 0.5), (0.35, 0.7), (1.0, 1.0)],
+            Interpolation::Linear,
+        );
+        assert_points(
+            &curve.quantize(0.25).unwrap(),
+            &[(0.0, 0.0), (0.25, 0.5), (1.0, 1.0)],
+        );
+    }
+
+    #[test]
+    fn smooth_and_simplify() {
+        let peak = curve(&[(0.0, 0.0), (0.5, 1.0), (1.0, 0.0)], Interpolation::Linear);
+        assert_points(&peak.smooth(1.0), &[(0.0, 0.0), (0.5, 0.0), (1.0, 0.0)]);
+        assert_points(&peak.smooth(0.0), &points(&peak));
+
+        let bend = curve(
+            &[(0.0, 0.0), (0.25, 0.25), (0.5, 0.5), (1.0, 0.0)],
+            Interpolation::Linear,
+        );
+        assert_points(&bend.simplify(0.01), &[(0.0, 0.0), (0.5, 0.5), (1.0, 0.0)]);
+    }
+}
@@ -0,0 +1,499 @@
+use nih_plug::prelude::{Buffer, Smoother, SmoothingStyle};
+use std::f32::consts::FRAC_PI_2;
+use std::ops::Range;
+
+use super::history::History;
+use super::resample::{Kernel, Resampling};
+use super::table::CurveTable;
+use super::Timing;
+use crate::curve::Length;
+
+/// The longest stretch of audio the beat engine keeps around, in seconds.
+/// Curves that would loop over any longer than this are shortened to fit, see
+/// [`BeatEngine::loop_length`].
+const MAX_HISTORY: f32 = 20.0;
+
+/// How long it takes to follow a scratch, and to fade between the scratch and
+/// the curve, in milliseconds.
+const SCRATCH_SMOOTHING_MS: f32 = 20.0;
+
+/// How far, in samples, the read head may move from one sample to the next
+/// before it counts as a jump and is crossfaded.
+const MAX_READ_STEP: f32 = 16.0;
+
+/// How much, in samples, the distance the read head moves may change from one
+/// sample to the next before it counts as a jump. This catches the jumps that
+/// are too short for [`MAX_READ_STEP`], while the bends at the nodes of a curve
+/// still play through.
+const MAX_STEP_CHANGE: f32 = 2.0;
+
+/// How many old read heads can be fading out at once. Any more and the
+/// quietest one is cut off.
+const MAX_FADING_HEADS: usize = 2;
+
+/// How far the current read head has to have faded in before another jump
+/// starts a new crossfade. Jumps come at every sample while the head sweeps
+/// quickly, and restarting the fade at each of them would keep the head from
+/// ever being heard.
+const MIN_FADE_BEFORE_JUMP: f32 = 0.5;
+
+/// A read head that's fading out after a jump.
+#[derive(Clone, Copy)]
+struct FadingHead {
+    /// The delay the head reads at. It keeps playing at normal speed.
+    delay: f32,
+
+    /// The gain of the head when it started fading out. This is below 1 if it
+    /// was still fading in at the time.
+    level: f32,
+
+    /// How far along the fade is, from 0 to 1. At 1 the head is silent.
+    fade: f32,
+}
+
+impl Default for FadingHead {
+    fn default() -> Self {
+        Self {
+            delay: 0.0,
+            level: 0.0,
+            fade: 1.0,
+        }
+    }
+}
+
+impl FadingHead {
+    /// The gain of the head at the current point in its fade.
+    fn gain(&self) -> f32 {
+        if self.fade < 1.0 {
+            self.level * (self.fade * FRAC_PI_2).cos()
+        } else {
+            0.0
+        }
+    }
+}
+
+/// The crossfades between the current read head and the old ones it jumped
+/// away from.
+struct Crossfade {
+    /// How much further along every fade gets with each sample. At 1 or more,
+    /// jumps aren't crossfaded at all.
+    step: f32,
+
+    /// How far the current read head has faded in, from 0 to 1.
+    fade_in: f32,
+
+    /// The old read heads that are fading out.
+    heads: [FadingHead; MAX_FADING_HEADS],
+}
+
+impl Default for Crossfade {
+    fn default() -> Self {
+        Self {
+            step: 1.0,
+            fade_in: 1.0,
+            heads: Default::default(),
+        }
+    }
+}
+
+impl Crossfade {
+    /// Stops all fades.
+    fn reset(&mut self) {
+        self.fade_in = 1.0;
+        self.heads = Default::default();
+    }
+
+    /// Whether the current read head has faded in far enough for a jump to
+    /// start a new crossfade.
+    fn can_jump(&self) -> bool {
+        self.step < 1.0 && self.fade_in >= MIN_FADE_BEFORE_JUMP
+    }
+
+    /// Starts fading out the current read head, which reads at `delay`, from
+    /// its current gain, and starts fading in a new one.
+    fn jump(&mut self, delay: f32) {
+        if !self.can_jump() {
+            return;
+        }
+
+        let level = (self.fade_in * FRAC_PI_2).sin();
+        let quietest = self
+            .heads
+            .iter_mut()
+            .min_by(|a, b| a.gain().total_cmp(&b.gain()));
+        if let Some(head) = quietest.filter(|head| head.gain() < level) {
+            *head = FadingHead {
+                delay,
+                level,
+                fade: 0.0,
+            };
+        }
+
+        self.fade_in = 0.0;
+    }
+
+    /// The gain of the current read head for the next sample, along with the
+    /// delay and gain of every old one. This moves every fade along.
+    fn next(&mut self) -> (f32, [(f32, f32); MAX_FADING_HEADS]) {
+        let gain = (self.fade_in * FRAC_PI_2).sin();
+        let heads = self.heads.map(|head| (head.delay, head.gain()));
+
+        self.fade_in = (self.fade_in + self.step).min(1.0);
+        for head in &mut self.heads {
+            head.fade = (head.fade + self.step).min(1.0);
+        }
+
+        (gain, heads)
+    }
+}
+
+/// A read head into the history, which crossfades whenever it jumps.
+#[derive(Default)]
+struct ReadHead {
+    /// The delay the last sample was read at.
+    last_delay: f32,
+
+    /// How far the delay moved at the last sample, or zero right after a jump.
+    last_step: f32,
+
+    /// The crossfades after the last jumps of the head.
+    crossfade: Crossfade,
+}
+
+impl ReadHead {
+    /// Stops all fades, and has the head carry on from `delay` without
+    /// counting its next move as a jump.
+    fn reset(&mut self, delay: f32) {
+        self.last_delay = delay;
+        self.last_step = 0.0;
+        self.crossfade.reset();
+    }
+
+    /// Moves the head on to read the next sample at `delay`. This returns the
+    /// delay, speed and gain of every read that makes up the sample: the head
+    /// itself, and the old heads that are still fading out.
+    fn next(&mut self, delay: f32) -> [(f32, f32, f32); 1 + MAX_FADING_HEADS] {
+        // The read head moves a sample forwards every sample, minus however
+        // much further back it's reading. Moving too far at once, or suddenly
+        // moving at a very different pace, counts as a jump. A jump that comes
+        // too soon after the last one to be crossfaded is just played through.
+        let step = delay - self.last_delay;
+        let jumped = step.abs() > MAX_READ_STEP || (step - self.last_step).abs() > MAX_STEP_CHANGE;
+        let speed = if jumped { 1.0 } else { 1.0 - step };
+        if jumped {
+            self.crossfade.jump(self.last_delay);
+        }
+        self.last_delay = delay;
+        self.last_step = if jumped { 0.0 } else { step };
+
+        // The old read heads keep playing at normal speed while they fade out.
+        let (gain, fading) = self.crossfade.next();
+        let mut reads = [(delay, speed, gain); 1 + MAX_FADING_HEADS];
+        for (read, (head_delay, head_gain)) in reads[1..].iter_mut().zip(fading) {
+            *read = (head_delay, 1.0, head_gain);
+        }
+
+        reads
+    }
+}
+
+/// The beat or timestretch engine.
+///
+/// The incoming audio is recorded into a history buffer. The selected curve
+/// then maps every position within the loop to the position that should be
+/// heard instead, so that the identity curve plays the audio back untouched, a
+/// flat curve repeats a single instant, a curve at half the slope plays at half
+/// speed, and a falling curve plays in reverse. Since the future can't be read,
+/// any node above the identity line just plays the current audio.
+///
+/// While scratching, the engine reads back as far as it's told to instead,
+/// through a read head of its own. Scratching in and out crossfades between
+/// that head and the curve's at equal power, rather than sweeping from one
+/// delay to the other.
+///
+/// Whenever a read head jumps, be it at a vertical step in the curve, at the
+/// end of the loop or because another preset was picked, the old read head
+/// keeps playing and is crossfaded into the new one at equal power. A jump
+/// once the current head is halfway through fading in fades it out from
+/// there, alongside the one that was already fading out. Any sooner and the
+/// jump is played through, so that a head sweeping quickly, which jumps at
+/// every sample, still gets heard.
+#[derive(Default)]
+pub struct BeatEngine {
+    /// The recorded input.
+    history: History,
+
+    /// The value of the curve at every sample in the block.
+    values: Vec<f32>,
+
+    /// The sample rate, for the smoothers.
+    sample_rate: f32,
+
+    /// How far back a scratch reads, as a fraction of the loop.
+    scratch_offset: Smoother<f32>,
+
+    /// How much of the scratch is heard over the curve, from 0 to 1.
+    scratch_amount: Smoother<f32>,
+
+    /// Whether a scratch is held.
+    scratching: bool,
+
+    /// The read head following the curve.
+    curve_head: ReadHead,
+
+    /// The read head following the scratch.
+    scratch_head: ReadHead,
+}
+
+impl BeatEngine {
+    /// Allocates the history buffer and scratch space. This must be called
+    /// before processing.
+    pub fn initialize(&mut self, num_channels: usize, sample_rate: f32, max_block: usize) {
+        self.history
+            .resize(num_channels, (MAX_HISTORY * sample_rate) as usize);
+        self.values = vec![0.0; max_block];
+
+        self.sample_rate = sample_rate;
+        self.scratch_offset = Smoother::new(SmoothingStyle::Linear(SCRATCH_SMOOTHING_MS));
+        self.scratch_amount = Smoother::new(SmoothingStyle::Linear(SCRATCH_SMOOTHING_MS));
+        self.curve_head.reset(0.0);
+        self.scratch_head.reset(0.0);
+    }
+
+    /// Forgets all recorded audio and stops scratching.
+    pub fn reset(&mut self) {
+        self.history.clear();
+        self.scratch_offset.reset(0.0);
+        self.scratch_amount.reset(0.0);
+        self.scratching = false;
+        self.curve_head.reset(0.0);
+        self.scratch_head.reset(0.0);
+    }
+
+    /// Sets how audio is read in between samples.
+    pub fn set_resampling(&mut self, resampling: Resampling) {
+        self.history.set_resampling(resampling);
+    }
+
+    /// Sets how long a jump of the read head is crossfaded over, in
+    /// milliseconds. Jumps aren't crossfaded at all if this is zero.
+    pub fn set_crossfade(&mut self, ms: f32) {
+        let samples = ms / 1000.0 * self.sample_rate;
+        let step = if samples < 1.0 { 1.0 } else { 1.0 / samples };
+        self.curve_head.crossfade.step = step;
+        self.scratch_head.crossfade.step = step;
+    }
+
+    /// Starts or keeps scratching at a read offset, as a fraction of the loop,
+    /// or goes back to the curve if `None`. This takes effect from the next
+    /// processed sample.
+    pub fn scratch(&mut self, offset: Option<f32>) {
+        match offset {
+            Some(offset) => {
+                // A new scratch starts right where it's asked to, and only
+                // fades in.
+                if self.scratch_amount.previous_value() == 0.0 {
+                    self.scratch_offset.reset(offset);
+                } else {
+                    self.scratch_offset.set_target(self.sample_rate, offset);
+                }
+
+                if !self.scratching {
+                    self.scratch_amount.set_target(self.sample_rate, 1.0);
+                }
+            }
+            None if self.scratching => self.scratch_amount.set_target(self.sample_rate, 0.0),
+            None => {}
+        }
+
+        self.scratching = offset.is_some();
+    }
+
+    /// The length a curve is looped over with a given timing. This is the
+    /// curve's own length, cut down to as many of its units as the history can
+    /// hold at the current tempo.
+    pub fn loop_length(&self, timing: Option<Timing>, curve: Option<&CurveTable>) -> Length {
+        let length = curve.map_or(Length::default(), CurveTable::length);
+        timing.map_or(length, |timing| {
+            let max_beats = self.history.max_delay() as f64 * timing.beats_per_sample;
+            length.fit(timing.bar_beats, max_beats)
+        })
+    }
+
+    /// Remaps a range of samples in a block of audio according to a curve, or
+    /// to the scratch if there is one, and blends the result with the input by
+    /// `mix`. If there's no timing information, the audio is just recorded and
+    /// passed through.
+    pub fn process(
+        &mut self,
+        buffer: &mut Buffer,
+        range: Range<usize>,
+        timing: Option<Timing>,
+        curve: Option<&CurveTable>,
+        mix: &Smoother<f32>,
+    ) {
+        let length = self.loop_length(timing, curve);
+        let values = match (timing, curve, self.values.get_mut(range.clone())) {
+            (Some(timing), Some(curve), Some(values)) => {
+                timing.fill_phases(values, range.start, length);
+                curve.fill_values(values);
+                Some(&*values)
+            }
+            _ => None,
+        };
+
+        let frames = buffer.iter_samples().enumerate();
+        for (sample_idx, mut frame) in frames.skip(range.start).take(range.len()) {
+            let scratch_was_silent = self.scratch_amount.previous_value() == 0.0;
+            let scratch_offset = self.scratch_offset.next();
+            let scratch_amount = self.scratch_amount.next();
+            let mix = mix.next();
+
+            let (curve_delay, scratch_delay) = timing.map_or((0.0, 0.0), |timing| {
+                let loop_samples = timing.loop_samples(length);
+                let curve_delay = values.map_or(0.0, |values| {
+                    let x = timing.phase(sample_idx, length);
+                    let y = values[sample_idx - range.start].clamp(0.0, 1.0);
+                    (x - y).max(0.0) as f64 * loop_samples
+                });
+                let scratch_delay = scratch_offset.clamp(0.0, 1.0) as f64 * loop_samples;
+
+                (curve_delay as f32, scratch_delay as f32)
+            });
+
+            // The scratch head stays put until a scratch fades in, so that it
+            // starts out right where it's asked to.
+            if scratch_was_silent {
+                self.scratch_head.reset(scratch_delay);
+            }
+
+            // The kernels are the same for every channel, and only the reads
+            // that are heard need one.
+            let angle = scratch_amount.clamp(0.0, 1.0) * FRAC_PI_2;
+            let curve_reads = self
+                .curve_head
+                .next(curve_delay)
+                .map(|read| (read, angle.cos()));
+            let scratch_reads = (scratch_amount > 0.0).then(|| {
+                self.scratch_head
+                    .next(scratch_delay)
+                    .map(|read| (read, angle.sin()))
+            });
+            let kernels = curve_reads
+                .into_iter()
+                .chain(scratch_reads.into_iter().flatten())
+                .filter_map(|((delay, speed, gain), head_gain)| {
+                    let gain = gain * head_gain;
+                    (gain > 0.0).then(|| (self.history.kernel(delay, speed), gain))
+                });
+            let mut reads: [Option<(Kernel, f32)>; 2 * (1 + MAX_FADING_HEADS)] = Default::default();
+            for (read, kernel) in reads.iter_mut().zip(kernels) {
+                *read = Some(kernel);
+            }
+
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                let dry = *sample;
+                self.history.write(channel, dry);
+
+                let wet: f32 = reads
+                    .iter()
+                    .flatten()
+                    .map(|(kernel, gain)| self.history.read(channel, kernel) * gain)
+                    .sum();
+
+                *sample = dry + (wet - dry) * mix;
+            }
+
+            self.history.advance();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::{Curve, Interpolation, Node};
+    use std::f32::consts::TAU;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    /// The length of every processed block, 10 ms.
+    const BLOCK_LEN: usize = 480;
+
+    const TEMPO: f64 = 120.0;
+
+    /// The frequency of the sine played through the engine.
+    const FREQUENCY: f32 = 440.0;
+
+    /// Plays a sine through the engine with the identity curve, which passes it
+    /// through untouched, and returns the RMS of every block. Before every
+    /// block, the engine is told to scratch at the offset `scratch` gives for
+    /// its index.
+    fn block_rms(num_blocks: usize, scratch: impl Fn(usize) -> Option<f32>) -> Vec<f32> {
+        let identity = Curve::new(
+            vec![Node::new(0.0, 0.0), Node::new(1.0, 1.0)],
+            Interpolation::Linear,
+        )
+        .unwrap();
+        let table = CurveTable::new(&identity, Length::default());
+        let beats_per_sample = TEMPO / 60.0 / SAMPLE_RATE as f64;
+
+        let mut engine = BeatEngine::default();
+        engine.initialize(1, SAMPLE_RATE, BLOCK_LEN);
+        engine.set_crossfade(5.0);
+        let mix = Smoother::new(SmoothingStyle::None);
+        mix.reset(1.0);
+
+        (0..num_blocks)
+            .map(|block| {
+                let start = block * BLOCK_LEN;
+                let mut samples: Vec<f32> = (start..start + BLOCK_LEN)
+                    .map(|n| (TAU * FREQUENCY * n as f32 / SAMPLE_RATE).sin())
+                    .collect();
+
+                engine.scratch(scratch(block));
+                let timing = Timing::free(start as f64 * beats_per_sample, beats_per_sample);
+                let mut buffer = Buffer::default();
+                // SAFETY: The slice outlives the buffer, which is only used here.
+                unsafe {
+                    buffer.set_slices(BLOCK_LEN, |slices| {
+                        slices.clear();
+                        slices.push(&mut samples);
+                    });
+                }
+                engine.process(&mut buffer, 0..BLOCK_LEN, Some(timing), Some(&table), &mix);
+
+                let power = samples.iter().map(|sample| sample * sample).sum::<f32>();
+                (power / BLOCK_LEN as f32).sqrt()
+            })
+            .collect()
+    }
+
+    /// Asserts that no block is much quieter than the sine that went in, which
+    /// has an RMS of about 0.7. Crossfading between two heads that are out of
+    /// phase can dip a little below that, but not anywhere near silence.
+    fn assert_no_dropouts(rms: &[f32]) {
+        for (block, rms) in rms.iter().enumerate() {
+            assert!(*rms > 0.3, "block {block} dropped to an RMS of {rms}");
+        }
+    }
+
+    #[test]
+    fn scratching_in_and_out_keeps_playing() {
+        let rms = block_rms(500, |block| (300..400).contains(&block).then_some(0.5));
+        assert_no_dropouts(&rms);
+    }
+
+    #[test]
+    fn moving_the_scratch_around_keeps_playing() {
+        // A controller jumping between two positions every 30 ms makes the
+        // scratch sweep back and forth through a good part of the loop, once
+        // there's that much audio to read.
+        let rms = block_rms(400, |block| match block {
+            0..=299 => None,
+            _ if block / 3 % 2 == 0 => Some(0.2),
+            _ => Some(0.7),
+        });
+        assert_no_dropouts(&rms);
+    }
+}
@@ -0,0 +1,79 @@
+use nih_plug::prelude::Enum;
+use std::sync::Arc;
+
+use super::{Timing, DEFAULT_BAR_BEATS};
+
+/// What moves the curves forwards.
+#[derive(Enum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SyncMode {
+    /// Follow the host's transport, and pass the audio through while it's
+    /// stopped.
+    #[id = "host"]
+    #[name = "Host"]
+    Host,
+
+    /// Run at the given rate, whether or not the host is playing.
+    #[id = "free"]
+    #[name = "Free"]
+    Free,
+
+    /// Run at the given rate, and start over on every note.
+    #[id = "note"]
+    #[name = "Note"]
+    Note,
+}
+
+/// A clock that keeps running without the host, for the free-running sync
+/// modes. It counts quarter notes, with [`Timing::free`] putting four to a bar.
+#[derive(Default)]
+pub struct Clock {
+    /// The position at the start of the next block, in quarter notes.
+    pos_beats: f64,
+
+    /// How many quarter notes pass with each sample.
+    beats_per_sample: f64,
+}
+
+impl Clock {
+    /// Sets how many bars pass per second.
+    pub fn set_rate(&mut self, rate: f32, sample_rate: f32) {
+        self.beats_per_sample = rate as f64 * DEFAULT_BAR_BEATS / sample_rate as f64;
+    }
+
+    /// The timing of the next block.
+    pub fn timing(&self) -> Timing {
+        Timing::free(self.pos_beats, self.beats_per_sample)
+    }
+
+    /// Starts over from the beginning at a sample in the next block, and
+    /// returns the new timing of the block. Samples before `sample` aren't
+    /// meant to be read from it.
+    pub fn restart(&mut self, sample: usize) -> Timing {
+        self.pos_beats = -(sample as f64) * self.beats_per_sample;
+        self.timing()
+    }
+
+    /// Moves on past a block of `samples` samples.
+    pub fn advance(&mut self, samples: usize) {
+        self.pos_beats += samples as f64 * self.beats_per_sample;
+    }
+
+    /// Goes back to the beginning.
+    pub fn reset(&mut self) {
+        self.pos_beats = 0.0;
+    }
+}
+
+/// Parses a rate in hertz, or as the length of a bar in milliseconds if it
+/// ends in `ms`.
+pub fn s2v_rate() -> Arc<dyn Fn(&str) -> Option<f32> + Send + Sync> {
+    Arc::new(|string| {
+        let string = string.trim().to_lowercase();
+        if let Some(ms) = string.strip_suffix("ms") {
+            let ms: f32 = ms.trim().parse().ok()?;
+            (ms > 0.0).then(|| 1000.0 / ms)
+        } else {
+            string.trim_end_matches("hz").trim().parse().ok()
+        }
+    })
+}
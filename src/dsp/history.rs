@@ -0,0 +1,87 @@
+use super::resample::{Kernel, Resampler, Resampling};
+
+/// A ring buffer holding the most recent input of every channel, which the
+/// beat engine reads back from at arbitrary delays.
+#[derive(Default)]
+pub struct History {
+    /// One buffer per channel. These all have the same length.
+    channels: Vec<Vec<f32>>,
+
+    /// The position the next frame will be written to.
+    pos: usize,
+
+    /// Reads in between frames.
+    resampler: Resampler,
+}
+
+impl History {
+    /// Allocates room for `len` frames of `num_channels` channels. This must
+    /// not be called from the audio thread.
+    pub fn resize(&mut self, num_channels: usize, len: usize) {
+        self.channels = vec![vec![0.0; len.max(2)]; num_channels];
+        self.pos = 0;
+    }
+
+    /// Silences the buffer.
+    pub fn clear(&mut self) {
+        for channel in &mut self.channels {
+            channel.fill(0.0);
+        }
+
+        self.pos = 0;
+    }
+
+    /// The number of frames the buffer can hold.
+    pub fn len(&self) -> usize {
+        self.channels.first().map_or(0, Vec::len)
+    }
+
+    /// The longest delay, in samples, that can be read from the buffer.
+    pub fn max_delay(&self) -> f32 {
+        self.len().saturating_sub(2) as f32
+    }
+
+    /// Sets how the buffer is read in between frames.
+    pub fn set_resampling(&mut self, resampling: Resampling) {
+        self.resampler.resampling = resampling;
+    }
+
+    /// Writes a sample to the current frame of a channel.
+    pub fn write(&mut self, channel: usize, sample: f32) {
+        if let Some(buf) = self.channels.get_mut(channel) {
+            buf[self.pos] = sample;
+        }
+    }
+
+    /// Moves on to the next frame. Call this once all channels of the current
+    /// frame have been written.
+    pub fn advance(&mut self) {
+        self.pos += 1;
+        if self.pos >= self.len() {
+            self.pos = 0;
+        }
+    }
+
+    /// The kernel for reading `delay` samples behind the current frame while
+    /// moving through the buffer `speed` times faster than it's written. A
+    /// delay of zero reads the frame that was just written.
+    pub fn kernel(&self, delay: f32, speed: f32) -> Kernel {
+        let delay = delay.clamp(0.0, self.max_delay());
+        self.resampler.kernel(delay, speed)
+    }
+
+    /// Reads a channel with a kernel from [`Self::kernel`].
+    pub fn read(&self, channel: usize, kernel: &Kernel) -> f32 {
+        let Some(buf) = self.channels.get(channel) else {
+            return 0.0;
+        };
+
+        // The current frame is the newest one. The future and anything older
+        // than the buffer are held at the newest and the oldest frames.
+        let len = buf.len();
+        kernel.read(|back| {
+            let back = back.clamp(0, len as isize - 1) as usize;
+            buf[(self.pos + len - back) % len]
+        })
+    }
+}
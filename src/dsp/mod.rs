@@ -0,0 +1,108 @@
+pub mod beat;
+pub mod clock;
+pub mod history;
+pub mod resample;
+pub mod scratch;
+pub mod table;
+pub mod trigger;
+pub mod vol;
+
+use nih_plug::prelude::Transport;
+
+use crate::curve::{Length, LengthUnit};
+
+/// The number of quarter notes in a bar when the host doesn't report a time
+/// signature, and in a bar of the free-running clock.
+const DEFAULT_BAR_BEATS: f64 = 4.0;
+
+/// Where the host transport or the free-running clock is during a block, and
+/// how fast it's moving.
+#[derive(Clone, Copy)]
+pub struct Timing {
+    /// The position at the start of the block, in quarter notes.
+    pub pos_beats: f64,
+
+    /// The position the current bar started at, in quarter notes.
+    pub bar_start: f64,
+
+    /// The number of the current bar, counting from zero. This is zero if the
+    /// host doesn't say.
+    pub bar_number: i32,
+
+    /// The length of a bar, in quarter notes.
+    pub bar_beats: f64,
+
+    /// How many quarter notes pass with each sample.
+    pub beats_per_sample: f64,
+}
+
+impl Timing {
+    /// Reads the timing from the host. Returns `None` if the transport isn't
+    /// playing or the host doesn't report a tempo or position.
+    pub fn from_transport(transport: &Transport) -> Option<Self> {
+        if !transport.playing {
+            return None;
+        }
+
+        let tempo = transport.tempo?;
+        let pos_beats = transport.pos_beats()?;
+
+        let bar_beats = match (transport.time_sig_numerator, transport.time_sig_denominator) {
+            (Some(num), Some(den)) if num > 0 && den > 0 => num as f64 * 4.0 / den as f64,
+            _ => DEFAULT_BAR_BEATS,
+        };
+
+        Some(Self {
+            pos_beats,
+            bar_start: transport.bar_start_pos_beats().unwrap_or(0.0),
+            bar_number: transport.bar_number().unwrap_or(0),
+            bar_beats,
+            beats_per_sample: tempo / 60.0 / transport.sample_rate as f64,
+        })
+    }
+
+    /// The timing of a free-running clock, with four quarter notes to a bar
+    /// and its first bar starting at zero.
+    pub fn free(pos_beats: f64, beats_per_sample: f64) -> Self {
+        Self {
+            pos_beats,
+            bar_start: 0.0,
+            bar_number: 0,
+            bar_beats: DEFAULT_BAR_BEATS,
+            beats_per_sample,
+        }
+    }
+
+    /// The position within a loop of a given length at a sample in the block,
+    /// from 0 to 1.
+    ///
+    /// Loops measured in bars follow the bars of the host, so that they keep
+    /// lining up with them when the time signature changes, and start on every
+    /// bar whose number is a multiple of their length. Other loops are counted
+    /// from the start of the song.
+    pub fn phase(&self, sample: usize, length: Length) -> f32 {
+        let pos = self.pos_beats + sample as f64 * self.beats_per_sample;
+        let loops = match length.unit {
+            LengthUnit::Bars => {
+                let bars = self.bar_number as f64 + (pos - self.bar_start) / self.bar_beats;
+                bars / length.count.clamp(1, Length::MAX_COUNT) as f64
+            }
+            _ => pos / length.beats(self.bar_beats),
+        };
+
+        loops.rem_euclid(1.0) as f32
+    }
+
+    /// Writes the position within a loop of every sample in a part of a block
+    /// starting at sample `start`.
+    pub fn fill_phases(&self, block: &mut [f32], start: usize, length: Length) {
+        for (sample_idx, phase) in block.iter_mut().enumerate() {
+            *phase = self.phase(start + sample_idx, length);
+        }
+    }
+
+    /// The number of samples in a loop of a given length.
+    pub fn loop_samples(&self, length: Length) -> f64 {
+        length.beats(self.bar_beats) / self.beats_per_sample
+    }
+}
@@ -0,0 +1,364 @@
+use nih_plug::prelude::Enum;
+use std::f32::consts::PI;
+
+/// The number of samples on either side of the read position the sinc kernel
+/// looks at.
+const SINC_HALF_TAPS: usize = 8;
+
+/// The number of samples the sinc kernel looks at.
+const SINC_TAPS: usize = SINC_HALF_TAPS * 2;
+
+/// The number of fractional positions the sinc kernel is precomputed for.
+/// Positions in between are interpolated.
+const SINC_PHASES: usize = 256;
+
+/// The cutoff of the sinc kernel, as a fraction of the Nyquist frequency. This
+/// leaves some room for the window's transition band.
+const SINC_CUTOFF: f32 = 0.9;
+
+/// How many times faster than normal the sinc kernel still lowers its cutoff
+/// for. Beyond this, reads alias.
+const MAX_SINC_SPEED: f32 = 8.0;
+
+/// How close to a sample, in samples, a read at normal speed or slower has to
+/// be for the sinc kernel to return that sample as is. Without this, reading
+/// the audio back untouched would still filter it.
+const SINC_EXACT_DISTANCE: f32 = 1e-4;
+
+/// How audio is read in between samples, much like [`Interpolation`] for
+/// curves. This matters whenever a beat curve's slope isn't exactly 1.
+///
+/// [`Interpolation`]: crate::curve::Interpolation
+#[derive(Enum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Resampling {
+    /// Draws straight lines between samples. This is cheap, but dulls the
+    /// highs and lets them alias.
+    #[id = "linear"]
+    #[name = "Linear"]
+    Linear,
+
+    /// Draws a cubic Hermite spline through the four nearest samples.
+    #[id = "cubic"]
+    #[name = "Cubic"]
+    Cubic,
+
+    /// Filters with a Blackman-windowed sinc, which keeps the highs and lowers
+    /// its cutoff to avoid aliasing when reading faster than normal.
+    #[id = "sinc"]
+    #[name = "Sinc"]
+    Sinc,
+}
+
+/// A Blackman-windowed sinc with a given cutoff, as a fraction of the Nyquist
+/// frequency, at `x` samples from its center.
+fn windowed_sinc(x: f32, cutoff: f32) -> f32 {
+    let half = SINC_HALF_TAPS as f32;
+    if x.abs() >= half {
+        return 0.0;
+    }
+
+    let sinc = if x == 0.0 {
+        cutoff
+    } else {
+        (PI * cutoff * x).sin() / (PI * x)
+    };
+    let t = PI * x / half;
+    let window = 0.42 + 0.5 * t.cos() + 0.08 * (2.0 * t).cos();
+    sinc * window
+}
+
+/// The sinc kernel for reading `frac` samples past a sample, with tap `i`
+/// weighing the sample `i - SINC_HALF_TAPS + 1` samples further back. The taps
+/// are normalized so that a constant signal stays the same.
+fn sinc_kernel(frac: f32, cutoff: f32) -> [f32; SINC_TAPS] {
+    let mut taps = [0.0; SINC_TAPS];
+    for (i, tap) in taps.iter_mut().enumerate() {
+        let offset = i as f32 - (SINC_HALF_TAPS - 1) as f32;
+        *tap = windowed_sinc(offset - frac, cutoff);
+    }
+
+    let sum: f32 = taps.iter().sum();
+    if sum.abs() > f32::EPSILON {
+        for tap in &mut taps {
+            *tap /= sum;
+        }
+    }
+
+    taps
+}
+
+/// The weights to read a signal with at a given position. These are the same
+/// for every channel, so they're worked out once per frame.
+#[derive(Clone, Copy)]
+pub struct Kernel {
+    /// How far back the sample under the first weight is.
+    first: isize,
+
+    /// How many of the weights are used.
+    len: usize,
+
+    /// The weight of every sample, from the newest to the oldest.
+    weights: [f32; SINC_TAPS],
+}
+
+impl Kernel {
+    /// A kernel that returns the sample `back` samples back as is.
+    fn exact(back: isize) -> Self {
+        let mut weights = [0.0; SINC_TAPS];
+        weights[0] = 1.0;
+        Self {
+            first: back,
+            len: 1,
+            weights,
+        }
+    }
+
+    /// Reads a signal, where `at(k)` is the sample `k` samples back. `at` may
+    /// be asked for samples past either end of the signal.
+    pub fn read(&self, at: impl Fn(isize) -> f32) -> f32 {
+        self.weights[..self.len]
+            .iter()
+            .enumerate()
+            .map(|(i, weight)| weight * at(self.first + i as isize))
+            .sum()
+    }
+}
+
+/// Reads signals in between samples with a [`Resampling`] kernel.
+pub struct Resampler {
+    /// The kernel to read with.
+    pub resampling: Resampling,
+
+    /// The sinc kernel at `SINC_PHASES + 1` evenly spaced fractional
+    /// positions, including both ends, at the regular cutoff.
+    sinc_table: Vec<[f32; SINC_TAPS]>,
+}
+
+impl Default for Resampler {
+    fn default() -> Self {
+        Self {
+            resampling: Resampling::Cubic,
+            sinc_table: (0..=SINC_PHASES)
+                .map(|phase| sinc_kernel(phase as f32 / SINC_PHASES as f32, SINC_CUTOFF))
+                .collect(),
+        }
+    }
+}
+
+impl Resampler {
+    /// The kernel for reading a signal `delay` samples back. The signal is
+    /// being read `speed` times faster than normal, which only the sinc kernel
+    /// makes use of.
+    pub fn kernel(&self, delay: f32, speed: f32) -> Kernel {
+        let whole = delay.floor();
+        let frac = delay - whole;
+        let whole = whole as isize;
+
+        let mut weights = [0.0; SINC_TAPS];
+        match self.resampling {
+            Resampling::Linear => {
+                weights[..2].copy_from_slice(&[1.0 - frac, frac]);
+                Kernel {
+                    first: whole,
+                    len: 2,
+                    weights,
+                }
+            }
+            Resampling::Cubic => {
+                // Catmull-Rom, with the weights of the samples before, at,
+                // after and two after the read position.
+                let (t, t2, t3) = (frac, frac * frac, frac * frac * frac);
+                weights[..4].copy_from_slice(&[
+                    -0.5 * t3 + t2 - 0.5 * t,
+                    1.5 * t3 - 2.5 * t2 + 1.0,
+                    -1.5 * t3 + 2.0 * t2 + 0.5 * t,
+                    0.5 * t3 - 0.5 * t2,
+                ]);
+                Kernel {
+                    first: whole - 1,
+                    len: 4,
+                    weights,
+                }
+            }
+            Resampling::Sinc => {
+                let speed = speed.abs().clamp(1.0, MAX_SINC_SPEED);
+                if speed == 1.0 && frac < SINC_EXACT_DISTANCE {
+                    return Kernel::exact(whole);
+                } else if speed == 1.0 && frac > 1.0 - SINC_EXACT_DISTANCE {
+                    return Kernel::exact(whole + 1);
+                }
+
+                let weights = if speed > 1.0 {
+                    // Reading faster moves everything up in pitch, so the
+                    // cutoff comes down to keep it below the Nyquist frequency.
+                    sinc_kernel(frac, SINC_CUTOFF / speed)
+                } else {
+                    let phase = frac * SINC_PHASES as f32;
+                    let index = (phase as usize).min(SINC_PHASES - 1);
+                    let t = phase - index as f32;
+
+                    let mut weights = self.sinc_table[index];
+                    for (weight, next) in weights.iter_mut().zip(&self.sinc_table[index + 1]) {
+                        *weight += (next - *weight) * t;
+                    }
+                    weights
+                };
+
+                Kernel {
+                    first: whole - (SINC_HALF_TAPS as isize - 1),
+                    len: SINC_TAPS,
+                    weights,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    /// The length of the sweep, in samples.
+    const SWEEP_LEN: usize = 4096;
+
+    /// A sine sweeping linearly from silence up to the Nyquist frequency.
+    fn sweep() -> Vec<f32> {
+        (0..SWEEP_LEN)
+            .map(|n| (PI * (n * n) as f64 / (2 * SWEEP_LEN) as f64).sin() as f32)
+            .collect()
+    }
+
+    /// The frequency of the sweep at position `pos`, as a fraction of the
+    /// sample rate.
+    fn sweep_frequency(pos: f64) -> f64 {
+        0.5 * pos / SWEEP_LEN as f64
+    }
+
+    /// Reads through a signal from start to end `speed` times faster than
+    /// normal, returning every read along with its position.
+    fn read_through(resampling: Resampling, signal: &[f32], speed: f32) -> Vec<(f64, f32)> {
+        let resampler = Resampler {
+            resampling,
+            ..Resampler::default()
+        };
+        let newest = signal.len() - 1;
+        let at = |back: isize| signal[newest - back.clamp(0, newest as isize) as usize];
+
+        // Leave room for the kernel on both ends.
+        let margin = SINC_TAPS as f64;
+        (0..)
+            .map(|n| margin + n as f64 * speed as f64)
+            .take_while(|&pos| pos < (newest as f64) - margin)
+            .map(|pos| {
+                let value = resampler
+                    .kernel((newest as f64 - pos) as f32, speed)
+                    .read(at);
+                (pos, value)
+            })
+            .collect()
+    }
+
+    /// The mean power of the reads whose position passes a test.
+    fn power(reads: &[(f64, f32)], test: impl Fn(f64) -> bool) -> f64 {
+        let (sum, count) = reads
+            .iter()
+            .filter(|(pos, _)| test(*pos))
+            .fold((0.0, 0), |(sum, count), (_, value)| {
+                (sum + (*value as f64).powi(2), count + 1)
+            });
+        sum / count as f64
+    }
+
+    /// The fraction of the power of the reads that lies above a frequency, as a
+    /// fraction of the sample rate. The reads are windowed to keep the edges
+    /// from smearing the spectrum.
+    fn power_above(reads: &[(f64, f32)], frequency: f64) -> f64 {
+        let len = reads.len();
+        let windowed: Vec<f64> = reads
+            .iter()
+            .enumerate()
+            .map(|(n, (_, value))| {
+                let window = 0.5 - 0.5 * (2.0 * PI * n as f64 / len as f64).cos();
+                *value as f64 * window
+            })
+            .collect();
+
+        let turns: Vec<(f64, f64)> = (0..len)
+            .map(|n| (2.0 * PI * n as f64 / len as f64).sin_cos())
+            .collect();
+
+        let (mut above, mut total) = (0.0, 0.0);
+        for bin in 1..len / 2 {
+            let (mut re, mut im) = (0.0, 0.0);
+            for (n, value) in windowed.iter().enumerate() {
+                let (sin, cos) = turns[bin * n % len];
+                re += value * cos;
+                im -= value * sin;
+            }
+
+            let bin_power = re * re + im * im;
+            total += bin_power;
+            if bin as f64 / len as f64 > frequency {
+                above += bin_power;
+            }
+        }
+
+        above / total
+    }
+
+    /// How much of the sweep ends up above the Nyquist frequency once it's
+    /// been read at `speed`. Reading slower leaves images of the sweep above
+    /// its new top, and reading faster folds everything above its new top
+    /// back down, where it's measured by how loud the reads past that point
+    /// are compared to the ones before.
+    fn aliasing(resampling: Resampling, speed: f32) -> f64 {
+        let reads = read_through(resampling, &sweep(), speed);
+        let new_nyquist = 0.5 / speed as f64;
+
+        if speed < 1.0 {
+            power_above(&reads, 0.5 * speed as f64 * 1.05)
+        } else {
+            let stopped = power(&reads, |pos| sweep_frequency(pos) > new_nyquist * 1.1);
+            let passed = power(&reads, |pos| sweep_frequency(pos) < new_nyquist * 0.8);
+            stopped / passed
+        }
+    }
+
+    #[test]
+    fn sweeps_stay_below_nyquist() {
+        for speed in [0.5, 1.5, 4.0] {
+            let linear = aliasing(Resampling::Linear, speed);
+            let cubic = aliasing(Resampling::Cubic, speed);
+            let sinc = aliasing(Resampling::Sinc, speed);
+
+            assert!(sinc < 0.02, "sinc lets {sinc} through at {speed}x");
+            assert!(
+                sinc < linear / 10.0 && sinc < cubic / 10.0,
+                "sinc lets {sinc} through at {speed}x, against {linear} for linear and \
+                 {cubic} for cubic",
+            );
+        }
+    }
+
+    #[test]
+    fn whole_delays_read_samples_as_is() {
+        let signal = sweep();
+        let newest = signal.len() - 1;
+        let at = |back: isize| signal[newest - back.clamp(0, newest as isize) as usize];
+
+        for resampling in [Resampling::Linear, Resampling::Cubic, Resampling::Sinc] {
+            let resampler = Resampler {
+                resampling,
+                ..Resampler::default()
+            };
+
+            for delay in [0, 1, 100, 2000] {
+                for speed in [0.5, 1.0] {
+                    let value = resampler.kernel(delay as f32, speed).read(at);
+                    assert_eq!(value, at(delay), "{resampling:?} at a delay of {delay}");
+                }
+            }
+        }
+    }
+}
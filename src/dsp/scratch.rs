@@ -0,0 +1,65 @@
+use nih_plug::prelude::{NoteEvent, SysExMessage};
+
+/// How far from its center the pitch bend has to be before it scratches.
+const BEND_DEAD_ZONE: f32 = 1e-3;
+
+/// The MIDI controller that scratches the beat engine.
+///
+/// A CC scratches while it's above zero and a pitch bend while it's away
+/// from its center. The further they go, the further back the engine reads,
+/// up to a whole loop.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ScratchControl {
+    /// Scratching is turned off.
+    Off,
+
+    /// A control change with the given number.
+    Cc(u8),
+
+    /// The pitch bend wheel.
+    PitchBend,
+}
+
+impl ScratchControl {
+    /// The number the controller is stored as.
+    pub fn to_raw(self) -> i32 {
+        match self {
+            Self::Off => -1,
+            Self::Cc(cc) => cc as i32,
+            Self::PitchBend => 128,
+        }
+    }
+
+    /// Reads a controller stored through [`Self::to_raw`].
+    pub fn from_raw(raw: i32) -> Self {
+        match raw {
+            0..=127 => Self::Cc(raw as u8),
+            128 => Self::PitchBend,
+            _ => Self::Off,
+        }
+    }
+
+    /// The controller that sent an event, if any, along with the read offset it
+    /// asks for as a fraction of the loop, or `None` if it's been let go of.
+    pub fn from_event<S: SysExMessage>(event: &NoteEvent<S>) -> Option<(Self, Option<f32>)> {
+        match *event {
+            NoteEvent::MidiCC { cc, value, .. } => {
+                Some((Self::Cc(cc), (value > 0.0).then_some(value)))
+            }
+            NoteEvent::MidiPitchBend { value, .. } => {
+                let offset = (value - 0.5).abs() * 2.0;
+                Some((Self::PitchBend, (offset > BEND_DEAD_ZONE).then_some(offset)))
+            }
+            _ => None,
+        }
+    }
+
+    /// A short name for the controller.
+    pub fn name(self) -> String {
+        match self {
+            Self::Off => String::from("Off"),
+            Self::Cc(cc) => format!("CC {cc}"),
+            Self::PitchBend => String::from("Bend"),
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use crate::curve::{Curve, Length, Preset};
+
+/// The number of segments a curve is split into in its lookup table.
+const TABLE_SIZE: usize = 4096;
+
+/// A curve sampled at evenly spaced points, so that the audio thread can
+/// evaluate it cheaply regardless of how many nodes it has.
+#[derive(Clone)]
+pub struct CurveTable {
+    /// The value of the curve at `TABLE_SIZE + 1` evenly spaced points,
+    /// including both endpoints.
+    values: Vec<f32>,
+
+    /// How long the curve lasts.
+    length: Length,
+}
+
+impl CurveTable {
+    /// Samples a curve that lasts a given length. This allocates, so it must
+    /// not be called from the audio thread.
+    pub fn new(curve: &Curve, length: Length) -> Self {
+        let mut values: Vec<f32> = (0..=TABLE_SIZE)
+            .map(|i| i as f32 / TABLE_SIZE as f32)
+            .collect();
+        curve.fill_values(&mut values);
+        Self { values, length }
+    }
+
+    /// How long the curve lasts.
+    pub fn length(&self) -> Length {
+        self.length
+    }
+
+    /// Evaluates the curve at `x`, interpolating linearly between the sampled
+    /// points.
+    pub fn value_at(&self, x: f32) -> f32 {
+        let pos = x.clamp(0.0, 1.0) * TABLE_SIZE as f32;
+        let idx = (pos as usize).min(TABLE_SIZE - 1);
+        let frac = pos - idx as f32;
+        self.values[idx] + (self.values[idx + 1] - self.values[idx]) * frac
+    }
+
+    /// Replaces every position in a block by the value of the curve there.
+    pub fn fill_values(&self, block: &mut [f32]) {
+        for x in block {
+            *x = self.value_at(*x);
+        }
+    }
+}
+
+/// The lookup tables for both preset banks, as seen by the audio thread.
+#[derive(Clone, Default)]
+pub struct Tables {
+    /// The tables for the beat presets. Presets with an empty curve have none.
+    beat: Vec<Option<CurveTable>>,
+
+    /// The tables for the volume presets. Presets with an empty curve have
+    /// none.
+    vol: Vec<Option<CurveTable>>,
+}
+
+/// Samples the curves of all presets in a bank.
+fn bank_tables(presets: &[Preset]) -> Vec<Option<CurveTable>> {
+    presets
+        .iter()
+        .map(|preset| {
+            (!preset.curve.is_empty()).then(|| CurveTable::new(&preset.curve, preset.length))
+        })
+        .collect()
+}
+
+impl Tables {
+    /// Samples both preset banks. This allocates, so it must not be called from
+    /// the audio thread.
+    pub fn new(beat_presets: &[Preset], vol_presets: &[Preset]) -> Self {
+        Self {
+            beat: bank_tables(beat_presets),
+            vol: bank_tables(vol_presets),
+        }
+    }
+
+    /// The table for the beat preset at a given index, if any.
+    pub fn beat(&self, index: usize) -> Option<&CurveTable> {
+        self.beat.get(index)?.as_ref()
+    }
+
+    /// The table for the volume preset at a given index, if any.
+    pub fn vol(&self, index: usize) -> Option<&CurveTable> {
+        self.vol.get(index)?.as_ref()
+    }
+}
@@ -0,0 +1,79 @@
+use std::ops::Range;
+
+use super::Timing;
+use crate::curve::Mode;
+
+/// The notes that pick beat presets, the lowest one picking the first slot.
+const BEAT_NOTES: Range<u8> = 24..60;
+
+/// The notes that pick volume presets, the lowest one picking the first slot.
+const VOL_NOTES: Range<u8> = 60..96;
+
+/// The mode and slot a note picks, if any.
+pub fn note_slot(note: u8) -> Option<(Mode, usize)> {
+    if BEAT_NOTES.contains(&note) {
+        Some((Mode::Beat, (note - BEAT_NOTES.start) as usize))
+    } else if VOL_NOTES.contains(&note) {
+        Some((Mode::Vol, (note - VOL_NOTES.start) as usize))
+    } else {
+        None
+    }
+}
+
+/// A preset picked by a held note. While the note is held, the preset plays
+/// from the start of its curve as of the moment the note was pressed, at the
+/// speed of the clock it would otherwise follow. Only the last note pressed
+/// counts, and releasing it goes back to the preset picked by the parameter.
+#[derive(Default)]
+pub struct Trigger {
+    /// The held note and the slot it picked.
+    held: Option<(u8, usize)>,
+
+    /// The position at the start of the next block, in quarter notes since
+    /// the note was pressed.
+    pos_beats: f64,
+}
+
+impl Trigger {
+    /// The slot to play, given the one picked by the parameter.
+    pub fn slot(&self, default: usize) -> usize {
+        self.held.map_or(default, |(_, slot)| slot)
+    }
+
+    /// The timing to play the slot with, given the one it would otherwise
+    /// follow.
+    pub fn timing(&self, timing: Option<Timing>) -> Option<Timing> {
+        match self.held {
+            Some(_) => timing.map(|timing| Timing {
+                pos_beats: self.pos_beats,
+                bar_start: 0.0,
+                bar_number: 0,
+                ..timing
+            }),
+            None => timing,
+        }
+    }
+
+    /// Plays a slot from the start at a sample in the next block.
+    pub fn press(&mut self, note: u8, slot: usize, sample: usize, beats_per_sample: f64) {
+        self.held = Some((note, slot));
+        self.pos_beats = -(sample as f64) * beats_per_sample;
+    }
+
+    /// Goes back to the parameter's slot if `note` is the held one.
+    pub fn release(&mut self, note: u8) {
+        if matches!(self.held, Some((held, _)) if held == note) {
+            self.held = None;
+        }
+    }
+
+    /// Moves on past a block of `samples` samples.
+    pub fn advance(&mut self, samples: usize, beats_per_sample: f64) {
+        self.pos_beats += samples as f64 * beats_per_sample;
+    }
+
+    /// Lets go of the held note.
+    pub fn reset(&mut self) {
+        self.held = None;
+    }
+}
@@ -0,0 +1,112 @@
+use nih_plug::prelude::{Buffer, Smoother};
+use std::ops::Range;
+
+use super::table::CurveTable;
+use super::Timing;
+
+/// The volume or gating engine.
+///
+/// Every sample is multiplied by the selected curve at the current position
+/// within the loop, so that a node at the top lets the audio through and a node
+/// at the bottom silences it. The gain follows the curve through an envelope,
+/// so that it can be made to open and close more gently than the curve does.
+pub struct VolEngine {
+    /// The gain for every sample in the block.
+    gains: Vec<f32>,
+
+    /// The sample rate, for the envelope.
+    sample_rate: f32,
+
+    /// How much of the way to the curve the gain moves every sample while
+    /// rising.
+    attack: f32,
+
+    /// How much of the way to the curve the gain moves every sample while
+    /// falling.
+    release: f32,
+
+    /// The gain applied to the last sample.
+    gain: f32,
+}
+
+impl Default for VolEngine {
+    fn default() -> Self {
+        Self {
+            gains: Vec::new(),
+            sample_rate: 44100.0,
+            attack: 1.0,
+            release: 1.0,
+            gain: 1.0,
+        }
+    }
+}
+
+/// How much of the way to its target a one-pole envelope moves every sample
+/// to get about two thirds of the way there in `ms` milliseconds.
+fn envelope_step(ms: f32, sample_rate: f32) -> f32 {
+    let samples = ms / 1000.0 * sample_rate;
+    if samples < 1.0 {
+        1.0
+    } else {
+        1.0 - (-1.0 / samples).exp()
+    }
+}
+
+impl VolEngine {
+    /// Allocates scratch space. This must be called before processing.
+    pub fn initialize(&mut self, sample_rate: f32, max_block: usize) {
+        self.gains = vec![0.0; max_block];
+        self.sample_rate = sample_rate;
+    }
+
+    /// Opens the gain back up.
+    pub fn reset(&mut self) {
+        self.gain = 1.0;
+    }
+
+    /// Sets how long the gain takes to rise and fall, in milliseconds.
+    pub fn set_envelope(&mut self, attack_ms: f32, release_ms: f32) {
+        self.attack = envelope_step(attack_ms, self.sample_rate);
+        self.release = envelope_step(release_ms, self.sample_rate);
+    }
+
+    /// Applies a curve as a gain to a range of samples in a block of audio,
+    /// blending its effect in by `mix`. If there's no timing information, the
+    /// gain opens up and the audio is passed through.
+    pub fn process(
+        &mut self,
+        buffer: &mut Buffer,
+        range: Range<usize>,
+        timing: Option<Timing>,
+        curve: Option<&CurveTable>,
+        mix: &Smoother<f32>,
+    ) {
+        let Some(gains) = self.gains.get_mut(range.clone()) else {
+            return;
+        };
+
+        match (timing, curve) {
+            (Some(timing), Some(curve)) => {
+                timing.fill_phases(gains, range.start, curve.length());
+                curve.fill_values(gains);
+            }
+            _ => gains.fill(1.0),
+        }
+
+        let frames = buffer.iter_samples().skip(range.start);
+        for (mut frame, target) in frames.zip(gains.iter()) {
+            let target = target.clamp(0.0, 1.0);
+            let step = if target > self.gain {
+                self.attack
+            } else {
+                self.release
+            };
+            self.gain += (target - self.gain) * step;
+
+            let gain = 1.0 + (self.gain - 1.0) * mix.next();
+            for sample in frame.iter_mut() {
+                *sample *= gain;
+            }
+        }
+    }
+}
@@ -0,0 +1,577 @@
+//! The factory preset banks that ship with Functor.
+//!
+//! Beat curves map the position within the bar to the position that's heard
+//! instead, so they can only ever stay on or below the diagonal. Volume curves
+//! give the gain at every position.
+
+use crate::curve::{Curve, Interpolation, Length, Mode, Node, Preset, JUMP};
+
+/// Builds a preset from a list of nodes.
+fn preset(name: &str, interpolation: Interpolation, nodes: Vec<Node>) -> Preset {
+    Preset {
+        name: name.to_owned(),
+        curve: Curve::new(nodes, interpolation).expect("factory presets should be valid"),
+        length: Length::default(),
+    }
+}
+
+/// A preset drawn with straight lines through a list of points.
+fn line(name: &str, points: &[(f32, f32)]) -> Preset {
+    let nodes = points.iter().map(|&(x, y)| Node::new(x, y)).collect();
+    preset(name, Interpolation::Linear, nodes)
+}
+
+/// A preset made out of consecutive straight pieces, each given as
+/// `(x0, x1, y0, y1)`, which jump from one to the next wherever they don't
+/// meet.
+fn pieces(name: &str, pieces: &[(f32, f32, f32, f32)]) -> Preset {
+    let mut nodes = Vec::new();
+
+    for (idx, &(x0, x1, y0, y1)) in pieces.iter().enumerate() {
+        let continues = nodes
+            .last()
+            .map_or(false, |node: &Node| node.x == x0 && node.y == y0);
+        if !continues {
+            nodes.push(Node::new(x0, y0));
+        }
+
+        match pieces.get(idx + 1) {
+            // Meet the next piece at its start.
+            Some(&(_, _, next_y0, _)) if next_y0 == y1 => nodes.push(Node::new(x1, y1)),
+
+            // Stop right before the next piece, which then jumps elsewhere.
+            Some(_) => {
+                let t = (x1 - JUMP - x0) / (x1 - x0);
+                nodes.push(Node::new(x1 - JUMP, y0 + (y1 - y0) * t));
+            }
+
+            None => nodes.push(Node::new(x1, y1)),
+        }
+    }
+
+    preset(name, Interpolation::Linear, nodes)
+}
+
+/// A beat preset splitting the bar into `steps` equal pieces, where piece `k`
+/// plays from `source(k)` onwards at normal speed.
+fn rearrange(name: &str, steps: usize, source: impl Fn(usize) -> f32) -> Preset {
+    let len = 1.0 / steps as f32;
+    let pieces: Vec<_> = (0..steps)
+        .map(|k| {
+            let x0 = k as f32 * len;
+            let y0 = source(k);
+            (x0, x0 + len, y0, y0 + len)
+        })
+        .collect();
+
+    self::pieces(name, &pieces)
+}
+
+/// A beat preset where each of the `steps` pieces of the bar plays the piece
+/// before it backwards. The first piece plays normally.
+fn reverse(name: &str, steps: usize) -> Preset {
+    let len = 1.0 / steps as f32;
+    let pieces: Vec<_> = (0..steps)
+        .map(|k| {
+            let x0 = k as f32 * len;
+            if k == 0 {
+                (x0, x0 + len, x0, x0 + len)
+            } else {
+                (x0, x0 + len, x0, x0 - len)
+            }
+        })
+        .collect();
+
+    self::pieces(name, &pieces)
+}
+
+/// A beat preset that splits the bar into `beats` pieces and repeats the first
+/// `1 / repeats` of each of them.
+fn stutter(name: &str, beats: usize, repeats: usize) -> Preset {
+    rearrange(name, beats * repeats, |k| {
+        (k / repeats) as f32 / beats as f32
+    })
+}
+
+/// The nodes of a beat curve that starts out on the diagonal at `start` and
+/// slows down steadily like a tape machine being switched off, until it plays
+/// at `end_speed` times normal speed at `end`. The position heard follows a
+/// parabola, so that it never gets ahead of the bar.
+fn slow_down(start: f32, end: f32, end_speed: f32) -> impl Iterator<Item = Node> {
+    const STEPS: usize = 8;
+
+    let span = end - start;
+    (0..=STEPS).map(move |step| {
+        let s = step as f32 / STEPS as f32;
+        let y = s - (1.0 - end_speed) * s * s / 2.0;
+        Node::new(start + span * s, start + span * y)
+    })
+}
+
+/// A beat preset that plays normally until `start` and then slows down to a
+/// halt at the end of the bar.
+fn stop(name: &str, start: f32) -> Preset {
+    let nodes = [Node::new(0.0, 0.0)]
+        .into_iter()
+        .chain(slow_down(start, 1.0, 0.0))
+        .collect();
+
+    preset(name, Interpolation::Linear, nodes)
+}
+
+/// A volume preset that steps through a pattern of `x` (open) and `.`
+/// (closed), each step staying open for a fraction `duty` of its length.
+fn gate(name: &str, pattern: &str, duty: f32) -> Preset {
+    let len = 1.0 / pattern.len() as f32;
+    let mut nodes = Vec::new();
+
+    for (k, step) in pattern.chars().enumerate() {
+        let x = k as f32 * len;
+        if step == 'x' {
+            nodes.push(Node::new(x, 1.0));
+            if duty < 1.0 {
+                nodes.push(Node::new(x + duty * len, 0.0));
+            }
+        } else {
+            nodes.push(Node::new(x, 0.0));
+        }
+    }
+
+    preset(name, Interpolation::Drop, nodes)
+}
+
+/// A sidechain-style volume preset, dipping down to `floor` every `period` and
+/// recovering after `release`, bent by `tension`.
+fn pump(name: &str, period: f32, floor: f32, release: f32, tension: f32) -> Preset {
+    let release = release.min(period - 2.0 * JUMP);
+    let mut nodes = Vec::new();
+
+    let count = (1.0 / period).round() as usize;
+    for k in 0..count {
+        let x = k as f32 * period;
+        if k > 0 {
+            nodes.push(Node::new(x - JUMP, 1.0));
+        }
+
+        nodes.push(Node {
+            tension,
+            ..Node::new(x, floor)
+        });
+        nodes.push(Node::new(x + release, 1.0));
+    }
+
+    preset(name, Interpolation::Linear, nodes)
+}
+
+/// A volume preset smoothly wobbling between full volume and `floor`, with
+/// `cycles` cycles per bar.
+fn tremolo(name: &str, cycles: usize, floor: f32) -> Preset {
+    let nodes = (0..=2 * cycles)
+        .map(|k| {
+            let y = if k % 2 == 0 { 1.0 } else { floor };
+            Node::new(k as f32 / (2 * cycles) as f32, y)
+        })
+        .collect();
+
+    preset(name, Interpolation::Cubic, nodes)
+}
+
+/// A volume preset with a sawtooth every `1 / cycles` of the bar, either
+/// rising or falling.
+fn saw(name: &str, cycles: usize, rising: bool) -> Preset {
+    let len = 1.0 / cycles as f32;
+    let (from, to) = if rising { (0.0, 1.0) } else { (1.0, 0.0) };
+    let pieces: Vec<_> = (0..cycles)
+        .map(|k| (k as f32 * len, (k + 1) as f32 * len, from, to))
+        .collect();
+
+    self::pieces(name, &pieces)
+}
+
+/// The preset new slots start out with, which leaves the audio untouched.
+pub fn init_preset(mode: Mode) -> Preset {
+    match mode {
+        Mode::Beat => line("Init", &[(0.0, 0.0), (1.0, 1.0)]),
+        Mode::Vol => line("Init", &[(0.0, 1.0), (1.0, 1.0)]),
+    }
+}
+
+/// The factory beat presets.
+pub fn beat_presets() -> Vec<Preset> {
+    vec![
+        line("Normal", &[(0.0, 0.0), (1.0, 1.0)]),
+        line("Half", &[(0.0, 0.0), (1.0, 0.5)]),
+        pieces("Half 2x", &[(0.0, 0.5, 0.0, 0.25), (0.5, 1.0, 0.5, 0.75)]),
+        line("Quarter", &[(0.0, 0.0), (1.0, 0.25)]),
+        stop("Stop", 0.5),
+        stop("Stop 1/4", 0.75),
+        preset(
+            "Start",
+            Interpolation::Linear,
+            vec![
+                Node {
+                    tension: 0.5,
+                    ..Node::new(0.0, 0.0)
+                },
+                Node::new(1.0, 1.0),
+            ],
+        ),
+        reverse("Rev beat", 4),
+        reverse("Rev half", 2),
+        reverse("Rev 1/8", 8),
+        stutter("Stut 1/4", 1, 4),
+        stutter("Stut 1/8", 4, 2),
+        stutter("Stut 1/16", 4, 4),
+        stutter("Stut 1/32", 4, 8),
+        pieces(
+            "Roll 1/8",
+            &[
+                (0.0, 0.75, 0.0, 0.75),
+                (0.75, 0.875, 0.75, 0.875),
+                (0.875, 1.0, 0.75, 0.875),
+            ],
+        ),
+        rearrange(
+            "Roll 1/16",
+            16,
+            |k| {
+                if k < 8 {
+                    k as f32 / 16.0
+                } else {
+                    0.5
+                }
+            },
+        ),
+        rearrange("Rep half", 2, |_| 0.0),
+        rearrange("Rep beat", 4, |k| k.min(2) as f32 / 4.0),
+        rearrange("Freeze", 64, |k| (k / 16) as f32 / 4.0),
+        preset(
+            "Scratch",
+            Interpolation::Linear,
+            (0..4)
+                .flat_map(|beat| {
+                    let b = beat as f32 / 4.0;
+                    [
+                        Node {
+                            tension: 0.3,
+                            ..Node::new(b, b)
+                        },
+                        Node {
+                            tension: -0.3,
+                            ..Node::new(b + 0.125, b + 0.1)
+                        },
+                        Node {
+                            tension: 0.5,
+                            ..Node::new(b + 0.1875, b + 0.03)
+                        },
+                    ]
+                })
+                .chain([Node::new(1.0, 1.0)])
+                .collect(),
+        ),
+        preset(
+            "Scratch 2",
+            Interpolation::Hermite,
+            (0..8)
+                .flat_map(|step| {
+                    let b = step as f32 / 8.0;
+                    [Node::new(b, b), Node::new(b + 0.0625, b + 0.05)]
+                })
+                .chain([Node::new(1.0, 1.0)])
+                .collect(),
+        ),
+        line("3/4 speed", &[(0.0, 0.0), (1.0, 0.75)]),
+        pieces("Double", &[(0.0, 0.5, 0.0, 0.5), (0.5, 1.0, 0.0, 1.0)]),
+        pieces(
+            "Double 2x",
+            &[
+                (0.0, 0.25, 0.0, 0.25),
+                (0.25, 0.5, 0.0, 0.5),
+                (0.5, 0.75, 0.5, 0.75),
+                (0.75, 1.0, 0.5, 1.0),
+            ],
+        ),
+        preset(
+            "Stops",
+            Interpolation::Linear,
+            (0..4)
+                .flat_map(|beat| {
+                    let b = beat as f32 / 4.0;
+                    slow_down(b, b + 0.25 - JUMP, 0.0)
+                })
+                .collect(),
+        ),
+        stutter("Stut 8T", 4, 3),
+        stutter("Stut 16T", 4, 6),
+        line("Half end", &[(0.0, 0.0), (0.75, 0.75), (1.0, 0.875)]),
+        preset(
+            "Slow ramp",
+            Interpolation::Linear,
+            slow_down(0.0, 1.0, 0.2).collect(),
+        ),
+        preset(
+            "Brake",
+            Interpolation::Linear,
+            [Node::new(0.0, 0.0)]
+                .into_iter()
+                .chain(slow_down(0.5, 0.7, 0.0))
+                .chain([Node::new(1.0, 0.6)])
+                .collect(),
+        ),
+        preset(
+            "Backspin",
+            Interpolation::Linear,
+            vec![
+                Node::new(0.0, 0.0),
+                Node {
+                    tension: -0.5,
+                    ..Node::new(0.75, 0.75)
+                },
+                Node::new(1.0, 0.0),
+            ],
+        ),
+        pieces(
+            "Delay 1/4",
+            &[(0.0, 0.25, 0.0, 0.25), (0.25, 1.0, 0.0, 0.75)],
+        ),
+        line("Delay 1/8", &[(0.0, 0.0), (0.125, 0.0), (1.0, 0.875)]),
+        pieces(
+            "Swing",
+            &(0..4)
+                .flat_map(|beat| {
+                    let b = beat as f32 / 4.0;
+                    [
+                        (b, b + 1.0 / 6.0, b, b + 0.125),
+                        (b + 1.0 / 6.0, b + 0.25, b + 0.125, b + 0.25),
+                    ]
+                })
+                .collect::<Vec<_>>(),
+        ),
+        preset(
+            "Drunk",
+            Interpolation::Hermite,
+            vec![
+                Node::new(0.0, 0.0),
+                Node::new(0.2, 0.15),
+                Node::new(0.35, 0.33),
+                Node::new(0.55, 0.45),
+                Node::new(0.7, 0.68),
+                Node::new(0.85, 0.78),
+                Node::new(1.0, 1.0),
+            ],
+        ),
+        rearrange("Glitch", 16, |k| {
+            const SOURCES: [usize; 16] = [0, 1, 0, 1, 4, 4, 4, 7, 8, 9, 8, 8, 12, 12, 13, 12];
+            SOURCES[k] as f32 / 16.0
+        }),
+    ]
+}
+
+/// The factory volume presets.
+pub fn vol_presets() -> Vec<Preset> {
+    vec![
+        line("Open", &[(0.0, 1.0), (1.0, 1.0)]),
+        gate("Gate 1/8", "xxxxxxxx", 0.5),
+        gate("Gate 1/16", "xxxxxxxxxxxxxxxx", 0.5),
+        gate("Trance A", "x.xxx.x.xx.xx.x.", 0.9),
+        gate("Trance B", "xx.x.xx.x.xxx.xx", 0.9),
+        gate("Gate 8T", "xxxxxxxxxxxx", 0.5),
+        gate("Gate 16T", "xxxxxxxxxxxxxxxxxxxxxxxx", 0.5),
+        pump("Pump 1/4", 0.25, 0.0, 0.15, -0.4),
+        pump("Pump 1/8", 0.125, 0.0, 0.08, -0.4),
+        pump("Pump soft", 0.25, 0.4, 0.15, -0.4),
+        pump("Pump long", 0.25, 0.0, 0.24, 0.4),
+        pump("Pump 1/2", 0.5, 0.0, 0.3, -0.4),
+        gate("Offbeat", ".x.x.x.x", 1.0),
+        preset(
+            "Fade in",
+            Interpolation::Linear,
+            vec![
+                Node {
+                    tension: 0.5,
+                    ..Node::new(0.0, 0.0)
+                },
+                Node::new(1.0, 1.0),
+            ],
+        ),
+        preset(
+            "Fade out",
+            Interpolation::Linear,
+            vec![
+                Node {
+                    tension: 0.3,
+                    ..Node::new(0.0, 1.0)
+                },
+                Node::new(1.0, 0.0),
+            ],
+        ),
+        preset(
+            "Swell",
+            Interpolation::Cubic,
+            vec![
+                Node::new(0.0, 0.0),
+                Node::new(0.5, 1.0),
+                Node::new(1.0, 0.0),
+            ],
+        ),
+        tremolo("Trem 1/8", 8, 0.2),
+        tremolo("Trem 1/16", 16, 0.2),
+        tremolo("Trem 8T", 12, 0.2),
+        saw("Saw dn 4", 4, false),
+        saw("Saw up 4", 4, true),
+        saw("Saw dn 8", 8, false),
+        gate("Square 4", "xxxx", 0.5),
+        gate("Stut gate", "xxxxxxxxxxxxxxxxxxxxxxxxx.x.x.x.", 1.0),
+        gate("Build", "x...x...x.x.x.x.xxxxxxxxxxxxxxxx", 0.5),
+        gate("Half mute", "x.", 1.0),
+        gate("Beat 1", "x...", 1.0),
+        gate("Backbeat", ".x.x", 1.0),
+        gate("Dotted", "x..x..x..x..x..x", 0.9),
+        gate("Clave", "x..x..x...x.x...", 0.9),
+        gate("Tresillo", "x..x..x.", 0.9),
+        gate("Euclid 5", "x..x.x..x.x..x.x", 0.9),
+        gate("Euclid 7", "x.x.x.x.x.x.x...", 0.9),
+        pump("Duck", 0.25, 0.0, 0.05, 0.0),
+        preset(
+            "Pump HQ",
+            Interpolation::Hermite,
+            (0..4)
+                .flat_map(|beat| {
+                    let b = beat as f32 / 4.0;
+                    [Node::new(b, 0.0), Node::new(b + 0.12, 0.9)]
+                })
+                .chain([Node::new(1.0, 1.0)])
+                .collect(),
+        ),
+        gate("Random", "xx.x...xx..xxx.x", 1.0),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsp::table::CurveTable;
+    use crate::dsp::Timing;
+    use crate::MAX_PRESETS;
+    use std::f32::consts::TAU;
+    use std::fmt::Write;
+    use std::path::Path;
+
+    /// The rendered output of every factory preset, as of the last time it
+    /// was deliberately changed. Run the tests with `UPDATE_SNAPSHOTS` set to
+    /// write it out again.
+    const SNAPSHOT: &str = include_str!("snapshots/factory.snap");
+
+    /// How many samples a bar lasts while rendering.
+    const BAR_SAMPLES: usize = 32;
+
+    /// How many cycles of the reference sine fit in a bar.
+    const REFERENCE_CYCLES: f32 = 3.0;
+
+    /// The reference signal at a position in samples, which may be fractional
+    /// since the beat engine reads between samples.
+    fn reference(pos: f32) -> f32 {
+        (pos / BAR_SAMPLES as f32 * REFERENCE_CYCLES * TAU).sin()
+    }
+
+    /// Plays a bar of the reference signal through a preset the way the
+    /// engines do, without any smoothing or crossfading.
+    fn render(mode: Mode, preset: &Preset) -> Vec<f32> {
+        let table = CurveTable::new(&preset.curve, preset.length);
+        let timing = Timing::free(0.0, 4.0 / BAR_SAMPLES as f64);
+        let loop_samples = timing.loop_samples(preset.length) as f32;
+
+        let mut values = vec![0.0; BAR_SAMPLES];
+        timing.fill_phases(&mut values, 0, preset.length);
+        table.fill_values(&mut values);
+
+        values
+            .iter()
+            .enumerate()
+            .map(|(sample_idx, &y)| {
+                let x = timing.phase(sample_idx, preset.length);
+                match mode {
+                    Mode::Beat => {
+                        let delay = (x - y.clamp(0.0, 1.0)).max(0.0) * loop_samples;
+                        reference(sample_idx as f32 - delay)
+                    }
+                    Mode::Vol => reference(sample_idx as f32) * y.clamp(0.0, 1.0),
+                }
+            })
+            .collect()
+    }
+
+    /// Both factory banks, along with a name for each.
+    fn banks() -> [(&'static str, Mode, Vec<Preset>); 2] {
+        [
+            ("beat", Mode::Beat, beat_presets()),
+            ("vol", Mode::Vol, vol_presets()),
+        ]
+    }
+
+    #[test]
+    fn init_presets_leave_audio_untouched() {
+        for mode in [Mode::Beat, Mode::Vol] {
+            let rendered = render(mode, &init_preset(mode));
+            for (sample_idx, sample) in rendered.into_iter().enumerate() {
+                assert!((sample - reference(sample_idx as f32)).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn banks_fit_the_preset_params() {
+        for (bank, _, presets) in banks() {
+            assert!(!presets.is_empty());
+            assert!(presets.len() <= MAX_PRESETS, "{bank} has {}", presets.len());
+
+            for (idx, preset) in presets.iter().enumerate() {
+                assert!(!preset.name.is_empty());
+                assert!(
+                    presets[..idx].iter().all(|other| other.name != preset.name),
+                    "{} is in the {bank} bank twice",
+                    preset.name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn beat_presets_stay_on_or_below_the_diagonal() {
+        for preset in beat_presets() {
+            for i in 0..=1000 {
+                let x = i as f32 / 1000.0;
+                let y = preset.curve.value_at(x);
+                assert!(y <= x + 1e-4, "{} is at {y} at {x}", preset.name);
+            }
+        }
+    }
+
+    #[test]
+    fn rendered_presets_match_the_snapshot() {
+        let mut rendered = String::new();
+        for (bank, mode, presets) in banks() {
+            for preset in presets {
+                write!(rendered, "{bank}/{}:", preset.name).unwrap();
+                for sample in render(mode, &preset) {
+                    write!(rendered, " {sample:.3}").unwrap();
+                }
+                rendered.push('\n');
+            }
+        }
+
+        if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            let path = Path::new(file!()).with_file_name("snapshots/factory.snap");
+            std::fs::write(path, &rendered).unwrap();
+            return;
+        }
+
+        for (line, expected) in rendered.lines().zip(SNAPSHOT.lines()) {
+            assert_eq!(
+                line, expected,
+                "run with UPDATE_SNAPSHOTS set if this is on purpose"
+            );
+        }
+        assert_eq!(rendered.lines().count(), SNAPSHOT.lines().count());
+    }
+}
@@ -0,0 +1,256 @@
+//! Reading and writing presets as files.
+//!
+//! Presets are stored as JSON, so that they can be shared and kept under version
+//! control. A file holds either a single preset along with the mode it's meant
+//! for:
+//!
+//! ```json
+//! {
+//!   "format": "functor-preset",
+//!   "version": 1,
+//!   "mode": "Beat",
+//!   "preset": {
+//!     "version": 3,
+//!     "name": "Half",
+//!     "curve": {
+//!       "nodes": [{ "x": 0.0, "y": 0.0, "tension": 0.0 }, { "x": 1.0, "y": 0.5, "tension": 0.0 }],
+//!       "interpolation": "Linear"
+//!     },
+//!     "length": { "count": 1, "unit": "Bars" }
+//!   }
+//! }
+//! ```
+//!
+//! or a whole bank, with `"format": "functor-bank"` and the beat and volume
+//! presets as lists under `"beat"` and `"vol"`.
+//!
+//! The same format is used to copy presets to the clipboard. Parts of a curve
+//! are copied with `"format": "functor-nodes"` and just the list of nodes under
+//! `"nodes"`.
+//!
+//! The outer `version` is that of the file layout, while each preset carries
+//! the version of its own schema, which is upgraded on load as described in the
+//! [`migrate`][crate::migrate] module.
+//!
+//! A curve is a list of nodes with coordinates in the unit square and strictly
+//! increasing `x`. Each node's `tension`, from -1 to 1, bends the segment that
+//! starts at it. The `interpolation` is one of `"Drop"`, `"Linear"`, `"Cubic"`
+//! or `"Hermite"`. Curves that break these rules are rejected on load.
+//!
+//! The `length` is how long the curve takes to play through once. Its `unit` is
+//! one of `"Bars"`, in the host's time signature, `"Beats"`, `"Eighths"`,
+//! `"Sixteenths"`, `"QuarterTriplets"` or `"EighthTriplets"`.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::curve::{Mode, ModePreset, Node, Preset};
+
+/// The version of the file format that's written. Files with a newer version
+/// are rejected.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// The file extension for presets and banks.
+pub const EXTENSION: &str = "json";
+
+/// Both preset banks.
+#[derive(Clone, Default)]
+pub struct Bank {
+    /// The beat presets.
+    pub beat: Vec<Preset>,
+
+    /// The volume presets.
+    pub vol: Vec<Preset>,
+}
+
+/// Something that's been copied to the clipboard.
+pub enum Clip {
+    /// A whole preset.
+    Preset(ModePreset),
+
+    /// Part of a curve.
+    Nodes(Vec<Node>),
+}
+
+/// The contents of a file.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "format")]
+enum File {
+    /// A single preset.
+    #[serde(rename = "functor-preset")]
+    Preset {
+        version: u32,
+        mode: Mode,
+        preset: Preset,
+    },
+
+    /// Both preset banks.
+    #[serde(rename = "functor-bank")]
+    Bank {
+        version: u32,
+        beat: Vec<Preset>,
+        vol: Vec<Preset>,
+    },
+
+    /// Part of a curve, which is only ever copied to the clipboard.
+    #[serde(rename = "functor-nodes")]
+    Nodes { version: u32, nodes: Vec<Node> },
+}
+
+/// A reason why a file couldn't be read or written.
+#[derive(Debug)]
+pub enum FileError {
+    /// The file couldn't be accessed.
+    Io(io::Error),
+
+    /// The file isn't valid JSON, or doesn't describe a valid preset.
+    Json(serde_json::Error),
+
+    /// The file holds a preset where a bank was expected, or vice versa.
+    WrongFormat,
+
+    /// The file was written by a newer version of Functor.
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Json(err) => write!(f, "{err}"),
+            Self::WrongFormat => write!(f, "the file holds a different kind of preset"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported format version {version}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for FileError {}
+
+impl From<io::Error> for FileError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for FileError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Json(err)
+    }
+}
+
+/// Reads a file and checks its version.
+fn read(path: &Path) -> Result<File, FileError> {
+    parse(&fs::read_to_string(path)?)
+}
+
+/// Parses the contents of a file and checks its version.
+fn parse(text: &str) -> Result<File, FileError> {
+    let file: File = serde_json::from_str(text)?;
+    let version = match &file {
+        File::Preset { version, .. } | File::Bank { version, .. } | File::Nodes { version, .. } => {
+            *version
+        }
+    };
+
+    if version > FORMAT_VERSION {
+        Err(FileError::UnsupportedVersion(version))
+    } else {
+        Ok(file)
+    }
+}
+
+/// Writes a file.
+fn write(path: &Path, file: &File) -> Result<(), FileError> {
+    fs::write(path, serde_json::to_string_pretty(file)?)?;
+    Ok(())
+}
+
+/// Loads a single preset.
+pub fn load_preset(path: &Path) -> Result<ModePreset, FileError> {
+    match read(path)? {
+        File::Preset { mode, preset, .. } => Ok(ModePreset { preset, mode }),
+        File::Bank { .. } | File::Nodes { .. } => Err(FileError::WrongFormat),
+    }
+}
+
+/// The name of the file to suggest when saving a preset with a given name.
+/// Characters that separate paths or aren't allowed in file names on some
+/// systems are replaced by dashes.
+pub fn preset_file_name(name: &str) -> String {
+    let stem: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '-',
+            c if c.is_control() => '-',
+            c => c,
+        })
+        .collect();
+
+    // Windows also doesn't allow names ending in a dot or a space.
+    let stem = stem.trim().trim_end_matches('.');
+    let stem = if stem.is_empty() { "Preset" } else { stem };
+    format!("{stem}.{EXTENSION}")
+}
+
+/// Saves a single preset.
+pub fn save_preset(path: &Path, preset: &ModePreset) -> Result<(), FileError> {
+    write(
+        path,
+        &File::Preset {
+            version: FORMAT_VERSION,
+            mode: preset.mode,
+            preset: preset.preset.clone(),
+        },
+    )
+}
+
+/// Loads both preset banks.
+pub fn load_bank(path: &Path) -> Result<Bank, FileError> {
+    match read(path)? {
+        File::Bank { beat, vol, .. } => Ok(Bank { beat, vol }),
+        File::Preset { .. } | File::Nodes { .. } => Err(FileError::WrongFormat),
+    }
+}
+
+/// Saves both preset banks.
+pub fn save_bank(path: &Path, bank: &Bank) -> Result<(), FileError> {
+    write(
+        path,
+        &File::Bank {
+            version: FORMAT_VERSION,
+            beat: bank.beat.clone(),
+            vol: bank.vol.clone(),
+        },
+    )
+}
+
+/// Reads something copied to the clipboard.
+pub fn parse_clip(text: &str) -> Result<Clip, FileError> {
+    match parse(text)? {
+        File::Preset { mode, preset, .. } => Ok(Clip::Preset(ModePreset { preset, mode })),
+        File::Nodes { nodes, .. } => Ok(Clip::Nodes(nodes)),
+        File::Bank { .. } => Err(FileError::WrongFormat),
+    }
+}
+
+/// Turns something into text for the clipboard.
+pub fn format_clip(clip: &Clip) -> Result<String, FileError> {
+    let file = match clip {
+        Clip::Preset(preset) => File::Preset {
+            version: FORMAT_VERSION,
+            mode: preset.mode,
+            preset: preset.preset.clone(),
+        },
+        Clip::Nodes(nodes) => File::Nodes {
+            version: FORMAT_VERSION,
+            nodes: nodes.clone(),
+        },
+    };
+
+    Ok(serde_json::to_string_pretty(&file)?)
+}
@@ -0,0 +1,442 @@
+//! Curves generated from a handful of settings, as a starting point for new
+//! presets.
+//!
+//! All generators draw straight lines, and jump from one value to another with
+//! a gap of [`JUMP`] between the two nodes. Only random steps have a shape for
+//! beats; the others are volume shapes.
+
+use std::f32::consts::PI;
+
+use crate::curve::{Curve, CurveError, Interpolation, Mode, Node, JUMP};
+
+/// How many straight pieces make up half a cycle of a sine.
+const SINE_PIECES: usize = 8;
+
+/// The shape of a single cycle of an LFO.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LfoShape {
+    /// Rises and falls smoothly.
+    Sine,
+
+    /// Rises and then falls in a straight line.
+    Triangle,
+
+    /// Rises in a straight line and then drops.
+    Saw,
+
+    /// Switches between fully open and fully closed.
+    Square,
+}
+
+impl LfoShape {
+    /// The points making up one cycle, with positions from 0 to 1. The shape
+    /// spends a fraction `duty` of the cycle rising, or open for a square.
+    fn cycle(self, duty: f32) -> Vec<(f32, f32)> {
+        match self {
+            Self::Sine => {
+                let rise = (0..SINE_PIECES).map(|i| {
+                    let t = i as f32 / SINE_PIECES as f32;
+                    (t * duty, 0.5 - 0.5 * (PI * t).cos())
+                });
+                let fall = (0..SINE_PIECES).map(|i| {
+                    let t = i as f32 / SINE_PIECES as f32;
+                    (duty + t * (1.0 - duty), 0.5 + 0.5 * (PI * t).cos())
+                });
+                rise.chain(fall).chain([(1.0, 0.0)]).collect()
+            }
+            Self::Triangle => vec![(0.0, 0.0), (duty, 1.0), (1.0, 0.0)],
+            Self::Saw => vec![(0.0, 0.0), (1.0, 1.0)],
+            Self::Square => vec![(0.0, 1.0), (duty, 1.0), (duty, 0.0), (1.0, 0.0)],
+        }
+    }
+}
+
+/// A small deterministic random number generator, so that the same seed always
+/// gives the same pattern. This is SplitMix64.
+struct Random(u64);
+
+impl Random {
+    /// The next number, from 0 to 1.
+    fn next(&mut self) -> f32 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// Turns a list of points with increasing positions into a curve. Where points
+/// share a position, the curve jumps from the first value to the last.
+fn from_points(points: impl IntoIterator<Item = (f32, f32)>) -> Result<Curve, CurveError> {
+    let mut nodes: Vec<Node> = Vec::new();
+    for (x, y) in points {
+        let len = nodes.len();
+        match nodes.last() {
+            Some(last) if last.x >= x && last.y == y => {}
+            Some(last) if last.x >= x => {
+                if len >= 2 && nodes[len - 2].x >= x - JUMP * 1.5 {
+                    // Already jumping here, so just jump somewhere else.
+                    nodes[len - 1].y = y;
+                } else {
+                    // Make room for the jump by pulling the last node back
+                    // along the line leading up to it.
+                    if len >= 2 {
+                        let (before, last) = (nodes[len - 2], nodes[len - 1]);
+                        nodes[len - 1].y -= (last.y - before.y) * JUMP / (last.x - before.x);
+                    }
+                    nodes[len - 1].x = x - JUMP;
+                    nodes.push(Node::new(x, y));
+                }
+            }
+            _ => nodes.push(Node::new(x, y)),
+        }
+    }
+
+    // Jumps right at the start move the first node out of the unit square,
+    // and jumps right after another node may end up before it.
+    nodes.retain(|node| node.x >= 0.0);
+    nodes.dedup_by(|node, before| node.x <= before.x);
+
+    Curve::new(nodes, Interpolation::Linear)
+}
+
+impl Curve {
+    /// An LFO with `rate` cycles per bar, starting `phase` cycles in. See
+    /// [`LfoShape`] for `duty`, which is kept away from 0 and 1.
+    pub fn lfo(shape: LfoShape, rate: u32, phase: f32, duty: f32) -> Result<Self, CurveError> {
+        let rate = rate.max(1);
+        let phase = phase.rem_euclid(1.0);
+        let duty = duty.clamp(0.05, 0.95);
+        let cycle = shape.cycle(duty);
+
+        // Lay out enough cycles to cover the bar, then cut them off at its
+        // ends.
+        let points: Vec<(f32, f32)> = (-1..=rate as i32)
+            .flat_map(|k| {
+                cycle
+                    .iter()
+                    .map(move |&(t, y)| ((k as f32 + t - phase) / rate as f32, y))
+            })
+            .collect();
+
+        // The value right after `x`, or right before it where the curve jumps
+        // if `before` is set.
+        let value_at = |x: f32, before: bool| {
+            let after = points
+                .partition_point(|&(px, _)| px < x || (px == x && !before))
+                .min(points.len() - 1);
+            let (x1, y1) = points[after];
+            let (x0, y0) = points[after.saturating_sub(1)];
+            if x1 > x0 {
+                y0 + (y1 - y0) * (x - x0) / (x1 - x0)
+            } else {
+                y1
+            }
+        };
+
+        let start = (0.0, value_at(0.0, false));
+        let end = (1.0, value_at(1.0, true));
+        let inside = points.iter().copied().filter(|&(x, _)| x > 0.0 && x < 1.0);
+        from_points([start].into_iter().chain(inside).chain([end]))
+    }
+
+    /// A gate that's open for `pulses` out of `steps` steps, spread out as
+    /// evenly as possible, and turned `rotation` steps to the right. Each pulse
+    /// stays open for a fraction `length` of its step.
+    pub fn euclidean(
+        pulses: u32,
+        steps: u32,
+        rotation: u32,
+        length: f32,
+    ) -> Result<Self, CurveError> {
+        let steps = steps.max(1);
+        let pulses = pulses.min(steps);
+        let length = length.clamp(0.1, 1.0);
+        let width = 1.0 / steps as f32;
+
+        let points = (0..steps).flat_map(|step| {
+            let source = (step + steps - rotation % steps) % steps;
+            let is_pulse = (source * pulses) % steps < pulses;
+            let start = step as f32 * width;
+
+            if is_pulse {
+                vec![
+                    (start, 1.0),
+                    (start + width * length, 1.0),
+                    (start + width * length, 0.0),
+                    (start + width, 0.0),
+                ]
+            } else {
+                vec![(start, 0.0), (start + width, 0.0)]
+            }
+        });
+
+        from_points(points)
+    }
+
+    /// A pattern of `steps` random steps, which is always the same for a given
+    /// seed. Volume steps each get a random level, while beat steps each play
+    /// from the start of a random step at or before them.
+    pub fn random_steps(steps: u32, seed: u64, mode: Mode) -> Result<Self, CurveError> {
+        let steps = steps.max(1);
+        let width = 1.0 / steps as f32;
+        let mut random = Random(seed);
+
+        let points = (0..steps).flat_map(|step| {
+            let start = step as f32 * width;
+            let (from, to) = match mode {
+                Mode::Beat => {
+                    let source = (random.next() * (step + 1) as f32) as u32;
+                    let source = source.min(step) as f32 * width;
+                    (source, source + width)
+                }
+                Mode::Vol => {
+                    let level = random.next();
+                    (level, level)
+                }
+            };
+
+            [(start, from), (start + width, to)]
+        });
+
+        from_points(points.collect::<Vec<_>>())
+    }
+
+    /// An envelope that's restarted on every one of `steps` steps. It rises to
+    /// the top over `attack`, falls to `sustain` over `decay`, and falls silent
+    /// over the last `release` of the step. The times are fractions of a step,
+    /// and are scaled down together if they add up to more than the step.
+    pub fn envelope(
+        steps: u32,
+        attack: f32,
+        decay: f32,
+        sustain: f32,
+        release: f32,
+    ) -> Result<Self, CurveError> {
+        let steps = steps.max(1);
+        let width = 1.0 / steps as f32;
+        let sustain = sustain.clamp(0.0, 1.0);
+
+        let (attack, decay, release) = (attack.max(0.0), decay.max(0.0), release.max(0.0));
+        let total = attack + decay + release;
+        let scale = if total > 1.0 { 1.0 / total } else { 1.0 };
+        let (attack, decay, release) = (attack * scale, decay * scale, release * scale);
+
+        let points = (0..steps).flat_map(|step| {
+            let start = step as f32 * width;
+            [
+                (start, 0.0),
+                (start + attack * width, 1.0),
+                (start + (attack + decay) * width, sustain),
+                (start + (1.0 - release) * width, sustain),
+                (start + width, 0.0),
+            ]
+        });
+
+        from_points(points)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// How far apart two values may be to count as equal.
+    const EPSILON: f32 = 1e-4;
+
+    /// How far from the ends of a stretch values are sampled, to stay clear of
+    /// the jumps there.
+    const MARGIN: f32 = 2.0 * JUMP;
+
+    const SHAPES: [LfoShape; 4] = [
+        LfoShape::Sine,
+        LfoShape::Triangle,
+        LfoShape::Saw,
+        LfoShape::Square,
+    ];
+
+    /// Asserts that a curve has nodes at both ends of the bar, in order, and
+    /// never leaves the unit square.
+    fn assert_in_bounds(curve: &Curve) {
+        let nodes = curve.nodes();
+        assert_eq!(nodes[0].x, 0.0);
+        assert_eq!(nodes[nodes.len() - 1].x, 1.0);
+        for pair in nodes.windows(2) {
+            assert!(
+                pair[0].x < pair[1].x,
+                "{} comes after {}",
+                pair[0].x,
+                pair[1].x
+            );
+        }
+        for node in nodes {
+            assert!((0.0..=1.0).contains(&node.y), "{} is out of range", node.y);
+        }
+    }
+
+    /// Asserts that a curve never falls between `start` and `end`, or never
+    /// rises if `rising` is false.
+    fn assert_monotonic(curve: &Curve, start: f32, end: f32, rising: bool) {
+        let (start, end) = (start + MARGIN, end - MARGIN);
+        let values: Vec<f32> = (0..=32)
+            .map(|i| curve.value_at(start + (end - start) * i as f32 / 32.0))
+            .collect();
+        for pair in values.windows(2) {
+            let change = if rising {
+                pair[1] - pair[0]
+            } else {
+                pair[0] - pair[1]
+            };
+            assert!(
+                change > -EPSILON,
+                "{values:?} turns between {start} and {end}"
+            );
+        }
+    }
+
+    /// The pairs of neighbouring nodes that are joined by a line rather than a
+    /// jump.
+    fn lines(curve: &Curve) -> impl Iterator<Item = (Node, Node)> + '_ {
+        curve
+            .nodes()
+            .windows(2)
+            .map(|pair| (pair[0], pair[1]))
+            .filter(|(a, b)| b.x - a.x > JUMP * 1.5)
+    }
+
+    #[test]
+    fn generated_curves_stay_in_bounds() {
+        for shape in SHAPES {
+            for rate in 1..=8 {
+                for phase in [0.0, 0.3, 0.75, 1.5] {
+                    for duty in [0.0, 0.5, 1.0] {
+                        assert_in_bounds(&Curve::lfo(shape, rate, phase, duty).unwrap());
+                    }
+                }
+            }
+        }
+
+        for steps in [1, 5, 8, 16] {
+            for pulses in 0..=steps {
+                for rotation in 0..steps {
+                    for length in [0.1, 0.5, 1.0] {
+                        let curve = Curve::euclidean(pulses, steps, rotation, length).unwrap();
+                        assert_in_bounds(&curve);
+                    }
+                }
+            }
+        }
+
+        for seed in 0..50 {
+            for steps in 1..=16 {
+                for mode in [Mode::Beat, Mode::Vol] {
+                    assert_in_bounds(&Curve::random_steps(steps, seed, mode).unwrap());
+                }
+            }
+        }
+
+        for steps in [1, 4, 16] {
+            for (attack, decay, release) in [(0.0, 0.0, 0.0), (0.1, 0.3, 0.2), (0.5, 0.5, 0.5)] {
+                let curve = Curve::envelope(steps, attack, decay, 0.6, release).unwrap();
+                assert_in_bounds(&curve);
+            }
+        }
+    }
+
+    #[test]
+    fn lfos_rise_for_the_duty_cycle_and_then_fall() {
+        for shape in SHAPES {
+            for rate in [1, 3, 8] {
+                for duty in [0.25, 0.5, 0.75] {
+                    let curve = Curve::lfo(shape, rate, 0.0, duty).unwrap();
+                    let width = 1.0 / rate as f32;
+                    for cycle in 0..rate {
+                        let start = cycle as f32 * width;
+                        let peak = start + duty * width;
+                        let end = start + width;
+                        match shape {
+                            LfoShape::Saw => assert_monotonic(&curve, start, end, true),
+                            _ => {
+                                assert_monotonic(&curve, start, peak, true);
+                                assert_monotonic(&curve, peak, end, false);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn euclidean_gates_open_once_per_pulse() {
+        for steps in [1, 5, 8, 16] {
+            for pulses in 0..=steps {
+                for rotation in [0, 1, 3] {
+                    let curve = Curve::euclidean(pulses, steps, rotation, 0.5).unwrap();
+                    assert!(curve.iter().all(|node| node.y == 0.0 || node.y == 1.0));
+
+                    // Pulses are open for the first half of their step.
+                    let width = 1.0 / steps as f32;
+                    let open = (0..steps)
+                        .filter(|&step| curve.value_at((step as f32 + 0.25) * width) == 1.0)
+                        .count();
+                    assert_eq!(open, pulses as usize);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn random_beats_play_forwards_from_the_past() {
+        for seed in 0..50 {
+            for steps in 1..=16 {
+                let curve = Curve::random_steps(steps, seed, Mode::Beat).unwrap();
+                for node in curve.iter() {
+                    assert!(
+                        node.y <= node.x + EPSILON,
+                        "{} is ahead of {}",
+                        node.y,
+                        node.x
+                    );
+                }
+                for (a, b) in lines(&curve) {
+                    assert!(((b.y - a.y) - (b.x - a.x)).abs() < EPSILON);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn random_volumes_hold_their_level() {
+        for seed in 0..50 {
+            for steps in 1..=16 {
+                let curve = Curve::random_steps(steps, seed, Mode::Vol).unwrap();
+                for (a, b) in lines(&curve) {
+                    assert_eq!(a.y, b.y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn envelopes_rise_once_per_step_and_then_fall() {
+        for steps in [1, 4, 16] {
+            for (attack, decay, release) in [(0.1, 0.3, 0.2), (0.5, 0.5, 0.5)] {
+                let curve = Curve::envelope(steps, attack, decay, 0.6, release).unwrap();
+                let scale = 1.0 / f32::max(attack + decay + release, 1.0);
+                let width = 1.0 / steps as f32;
+                for step in 0..steps {
+                    let start = step as f32 * width;
+                    let peak = start + attack * scale * width;
+                    let end = start + width;
+                    assert!(curve.value_at(start) < EPSILON);
+                    assert!((curve.value_at(peak) - 1.0).abs() < EPSILON);
+                    assert_monotonic(&curve, start, peak, true);
+                    assert_monotonic(&curve, peak, end, false);
+                }
+            }
+        }
+    }
+}
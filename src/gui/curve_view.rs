@@ -1,95 +1,739 @@
+use nih_plug::nih_error;
 use nih_plug_vizia::vizia::{cache::BoundingBox, prelude::*, vg};
 
-use crate::curve::{Curve, Mode, Node, Preset};
+use super::undo::UndoEvent;
+use super::PresetEvent;
+use crate::curve::{Curve, Interpolation, Length, Mode, ModePreset, Node, Preset};
+use crate::file::{self, Clip};
 
-/// A list widget for the beats or volumes.
-pub struct CurveView<LBeat, LVol, LMode, LIndex>
+/// How close, in pixels, the mouse has to be to a node or handle to grab it.
+const HIT_RADIUS: f32 = 6.0;
+
+/// The width and height, in pixels, of the square drawn for every node.
+const NODE_SIZE: f32 = 10.0;
+
+/// The smallest horizontal gap that's kept between two nodes while dragging.
+const MIN_GAP: f32 = 1e-3;
+
+/// How much the tension changes when dragging a handle across the full height
+/// of the view.
+const TENSION_SPEED: f32 = 4.0;
+
+/// The number of horizontal grid lines, plus one.
+const GRID_ROWS: u32 = 4;
+
+/// The number of quarter notes in a bar, as far as the editor is concerned. It
+/// doesn't know the time signature of the host.
+pub const EDITOR_BAR_BEATS: f64 = 4.0;
+
+/// The closest vertical grid lines may be drawn together, in pixels.
+const MIN_GRID_SPACING: f32 = 4.0;
+
+/// The color of the lines between grid cells.
+const GRID_COLOR: vg::Color = vg::Color::rgbf(0.9, 0.9, 0.9);
+
+/// The color of the lines between beats.
+const BEAT_COLOR: vg::Color = vg::Color::rgbf(0.7, 0.7, 0.7);
+
+/// The color of the curve.
+const CURVE_COLOR: vg::Color = vg::Color::rgbf(0.45, 0.25, 0.8);
+
+/// The color under a volume curve.
+const FILL_COLOR: vg::Color = vg::Color::rgbaf(0.8, 0.6, 1.0, 0.5);
+
+/// The color of the playhead.
+const PLAYHEAD_COLOR: vg::Color = vg::Color::rgbf(1.0, 0.3, 0.3);
+
+/// The color of the selected range.
+const SELECTION_COLOR: vg::Color = vg::Color::rgbaf(0.45, 0.25, 0.8, 0.15);
+
+/// The grid that nodes snap to while editing, as a note value.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Data)]
+pub enum Snap {
+    /// Nodes are placed freely.
+    Off,
+
+    /// Quarter notes.
+    Quarter,
+
+    /// Eighth notes.
+    Eighth,
+
+    /// Sixteenth notes.
+    #[default]
+    Sixteenth,
+
+    /// Quarter note triplets.
+    QuarterTriplet,
+
+    /// Eighth note triplets.
+    EighthTriplet,
+
+    /// Sixteenth note triplets.
+    SixteenthTriplet,
+}
+
+impl Snap {
+    /// The number of grid cells in a bar of 4/4, if any.
+    pub fn divisions(self) -> Option<u32> {
+        match self {
+            Self::Off => None,
+            Self::Quarter => Some(4),
+            Self::Eighth => Some(8),
+            Self::Sixteenth => Some(16),
+            Self::QuarterTriplet => Some(6),
+            Self::EighthTriplet => Some(12),
+            Self::SixteenthTriplet => Some(24),
+        }
+    }
+
+    /// The width of a grid cell in a curve of a given length, if any.
+    pub fn cell(self, length: Length) -> Option<f32> {
+        let divisions = self.divisions()?;
+        let beats = length.beats(EDITOR_BAR_BEATS);
+        Some((EDITOR_BAR_BEATS / divisions as f64 / beats) as f32)
+    }
+
+    /// Rounds a position in a curve of a given length to the nearest grid
+    /// line.
+    pub fn snap(self, x: f32, length: Length) -> f32 {
+        match self.cell(length) {
+            Some(cell) => ((x / cell).round() * cell).clamp(0.0, 1.0),
+            None => x,
+        }
+    }
+}
+
+/// Where the audio thread is within the active curve of each mode, from 0 to
+/// 1, or negative numbers if it's not playing.
+#[derive(Clone, Copy, PartialEq, Data)]
+pub struct Playhead {
+    /// The position within the beat curve.
+    pub beat: f32,
+
+    /// The position within the volume curve.
+    pub vol: f32,
+}
+
+/// What's being dragged in a [`CurveView`].
+#[derive(Clone, Copy)]
+enum Drag {
+    /// The node with a given index.
+    Node(usize),
+
+    /// The tension handle of the segment starting at a given node.
+    Tension {
+        /// The index of the node starting the segment.
+        index: usize,
+
+        /// The vertical mouse position the drag started at.
+        start_y: f32,
+
+        /// The tension the drag started with.
+        start_tension: f32,
+    },
+
+    /// The end of the selected range.
+    Selection,
+}
+
+/// An editor for the curve of the selected preset.
+pub struct CurveView<LBeat, LVol, LMode, LIndex, LSnap, LPlayhead>
 where
     LBeat: Lens<Target = Vec<Preset>>,
     LVol: Lens<Target = Vec<Preset>>,
     LMode: Lens<Target = Mode>,
     LIndex: Lens<Target = usize>,
+    LSnap: Lens<Target = Snap>,
+    LPlayhead: Lens<Target = Playhead>,
 {
     beat_lens: LBeat,
     vol_lens: LVol,
     mode_lens: LMode,
     index_lens: LIndex,
+    snap_lens: LSnap,
+    playhead_lens: LPlayhead,
+
+    /// What's currently being dragged, if anything.
+    drag: Option<Drag>,
+
+    /// The range of `x` coordinates that's selected, from where the selection
+    /// started to where it ended.
+    selection: Option<(f32, f32)>,
 }
 
-impl<LBeat, LVol, LMode, LIndex> CurveView<LBeat, LVol, LMode, LIndex>
+impl<LBeat, LVol, LMode, LIndex, LSnap, LPlayhead>
+    CurveView<LBeat, LVol, LMode, LIndex, LSnap, LPlayhead>
 where
     LBeat: Lens<Target = Vec<Preset>>,
     LVol: Lens<Target = Vec<Preset>>,
     LMode: Lens<Target = Mode>,
     LIndex: Lens<Target = usize>,
+    LSnap: Lens<Target = Snap>,
+    LPlayhead: Lens<Target = Playhead>,
 {
-    /// Creates a new List view with a binding to the given lens and a template
-    /// for constructing the list items
+    /// Creates a new curve view, editing the preset selected through the given
+    /// lenses. The playhead is the position the audio thread is at, or a
+    /// negative number if it's not playing.
     pub fn new(
         cx: &mut Context,
         beat_lens: LBeat,
         vol_lens: LVol,
         mode_lens: LMode,
         index_lens: LIndex,
+        snap_lens: LSnap,
+        playhead_lens: LPlayhead,
     ) -> Handle<Self>
     where
         <LBeat as Lens>::Source: Model,
         <LVol as Lens>::Source: Model,
         <LMode as Lens>::Source: Model,
         <LIndex as Lens>::Source: Model,
+        <LSnap as Lens>::Source: Model,
+        <LPlayhead as Lens>::Source: Model,
     {
         Self {
-            beat_lens,
-            vol_lens,
-            mode_lens,
-            index_lens,
+            beat_lens: beat_lens.clone(),
+            vol_lens: vol_lens.clone(),
+            mode_lens: mode_lens.clone(),
+            index_lens: index_lens.clone(),
+            snap_lens: snap_lens.clone(),
+            playhead_lens: playhead_lens.clone(),
+            drag: None,
+            selection: None,
         }
         .build(cx, |_| {})
+        // Redraw whenever anything that's shown changes.
+        .bind(beat_lens, |handle, _| handle.cx.needs_redraw())
+        .bind(vol_lens, |handle, _| handle.cx.needs_redraw())
+        .bind(mode_lens, |handle, _| handle.cx.needs_redraw())
+        .bind(index_lens, |handle, _| handle.cx.needs_redraw())
+        .bind(snap_lens, |handle, _| handle.cx.needs_redraw())
+        .bind(playhead_lens, |handle, _| handle.cx.needs_redraw())
     }
 
-    pub fn curve(&self, cx: &mut DrawContext) -> Curve {
+    /// The selected preset, if there is one.
+    pub fn preset(&self, cx: &impl DataContext) -> Option<Preset> {
+        let index = self.index_lens.get(cx);
         match self.mode_lens.get(cx) {
-            Mode::Beat => self
-                .beat_lens
-                .clone()
-                .index(self.index_lens.get(cx))
-                .then(Preset::curve)
-                .get(cx),
+            Mode::Beat => self.beat_lens.clone().index(index).get_fallible(cx),
+            Mode::Vol => self.vol_lens.clone().index(index).get_fallible(cx),
+        }
+    }
+
+    /// Replaces the selected preset.
+    fn set_preset(&self, cx: &mut EventContext, preset: Preset) {
+        cx.emit(PresetEvent::Set {
+            mode: self.mode_lens.get(cx),
+            index: self.index_lens.get(cx),
+            preset,
+        });
+        cx.needs_redraw();
+    }
+
+    /// Starts dragging whatever is under the mouse. Clicking on an empty spot
+    /// adds a node there, and holding control selects a range instead.
+    fn grab(&mut self, cx: &mut EventContext) {
+        let Some(mut preset) = self.preset(cx) else {
+            return;
+        };
+
+        let bounds = cx.bounds();
+        let (mouse_x, mouse_y) = (cx.mouse().cursorx, cx.mouse().cursory);
+        let nodes = preset.curve.nodes();
+
+        if cx.modifiers().ctrl() {
+            let (x, _) = to_unit(bounds, mouse_x, mouse_y);
+            let x = self.snap_x(cx, x);
+            self.selection = Some((x, x));
+            self.drag = Some(Drag::Selection);
+            cx.needs_redraw();
+            return;
+        }
+
+        if self.selection.take().is_some() {
+            cx.needs_redraw();
+        }
+
+        if let Some(index) = node_at(bounds, nodes, mouse_x, mouse_y) {
+            self.drag = Some(Drag::Node(index));
+            return;
+        }
+
+        if preset.curve.interpolation != Interpolation::Drop {
+            if let Some(index) = handle_at(bounds, &preset, mouse_x, mouse_y) {
+                self.drag = Some(Drag::Tension {
+                    index,
+                    start_y: mouse_y,
+                    start_tension: nodes[index].tension,
+                });
+                return;
+            }
+        }
+
+        let (x, y) = to_unit(bounds, mouse_x, mouse_y);
+        let x = self.snap_x(cx, x);
+        let index = nodes.partition_point(|node| node.x < x);
+
+        // Grab any node that's already on the spot instead of stacking another
+        // one on it.
+        let existing = [index.checked_sub(1), Some(index)]
+            .into_iter()
+            .flatten()
+            .find(|&i| {
+                nodes
+                    .get(i)
+                    .map_or(false, |node| (node.x - x).abs() < MIN_GAP)
+            });
+        if let Some(existing) = existing {
+            self.drag = Some(Drag::Node(existing));
+            return;
+        }
+
+        let mut nodes = nodes.to_vec();
+        nodes.insert(index, Node::new(x, y));
+        let Ok(curve) = Curve::new(nodes, preset.curve.interpolation) else {
+            return;
+        };
+
+        preset.curve = curve;
+        self.drag = Some(Drag::Node(index));
+        self.set_preset(cx, preset);
+    }
+
+    /// Updates whatever is being dragged to the new mouse position.
+    fn drag(&mut self, cx: &mut EventContext) {
+        let (Some(drag), Some(mut preset)) = (self.drag, self.preset(cx)) else {
+            return;
+        };
+
+        let bounds = cx.bounds();
+        let (mouse_x, mouse_y) = (cx.mouse().cursorx, cx.mouse().cursory);
+        let mut nodes = preset.curve.nodes().to_vec();
+
+        match drag {
+            Drag::Node(index) => {
+                if index >= nodes.len() {
+                    return;
+                }
+
+                let (x, y) = to_unit(bounds, mouse_x, mouse_y);
+
+                // The endpoints only ever move vertically.
+                let last = nodes.len() - 1;
+                if index != 0 && index != last {
+                    let min = nodes[index - 1].x + MIN_GAP;
+                    let max = nodes[index + 1].x - MIN_GAP;
+                    if min <= max {
+                        nodes[index].x = self.snap_x(cx, x).clamp(min, max);
+                    }
+                }
+
+                nodes[index].y = y;
+            }
+
+            Drag::Tension {
+                index,
+                start_y,
+                start_tension,
+            } => {
+                let (Some(a), Some(b)) = (nodes.get(index), nodes.get(index + 1)) else {
+                    return;
+                };
+
+                // Dragging upwards always bows the segment upwards.
+                let direction = if b.y >= a.y { 1.0 } else { -1.0 };
+                let delta = (start_y - mouse_y) / bounds.h * TENSION_SPEED;
+                nodes[index].tension = (start_tension - direction * delta).clamp(-1.0, 1.0);
+            }
+
+            Drag::Selection => {
+                let (x, _) = to_unit(bounds, mouse_x, mouse_y);
+                let x = self.snap_x(cx, x);
+                if let Some((_, end)) = &mut self.selection {
+                    *end = x;
+                }
+
+                cx.needs_redraw();
+                return;
+            }
+        }
+
+        match Curve::new(nodes, preset.curve.interpolation) {
+            Ok(curve) => preset.curve = curve,
+            Err(_) => return,
+        }
+        self.set_preset(cx, preset);
+    }
+
+    /// Deletes the node under the mouse, unless it's an endpoint.
+    fn delete(&mut self, cx: &mut EventContext) {
+        let Some(mut preset) = self.preset(cx) else {
+            return;
+        };
+
+        let bounds = cx.bounds();
+        let (mouse_x, mouse_y) = (cx.mouse().cursorx, cx.mouse().cursory);
+        let mut nodes = preset.curve.nodes().to_vec();
+
+        if let Some(index) = node_at(bounds, &nodes, mouse_x, mouse_y) {
+            if index != 0 && index != nodes.len() - 1 {
+                nodes.remove(index);
+                if let Ok(curve) = Curve::new(nodes, preset.curve.interpolation) {
+                    preset.curve = curve;
+                    self.drag = None;
+                    self.set_preset(cx, preset);
+                }
+            }
+        }
+    }
+
+    /// The selected range, from left to right, if any.
+    fn selected_range(&self) -> Option<(f32, f32)> {
+        self.selection
+            .map(|(start, end)| (start.min(end), start.max(end)))
+    }
+
+    /// Copies the nodes in the selected range to the clipboard, or the whole
+    /// preset if nothing is selected.
+    fn copy(&self, cx: &mut EventContext) {
+        let Some(preset) = self.preset(cx) else {
+            return;
+        };
+
+        let clip = match self.selected_range() {
+            Some((start, end)) if start < end => {
+                let nodes: Vec<Node> = preset
+                    .curve
+                    .iter()
+                    .filter(|node| (start..=end).contains(&node.x))
+                    .copied()
+                    .collect();
+                if nodes.is_empty() {
+                    return;
+                }
+
+                Clip::Nodes(nodes)
+            }
+            _ => Clip::Preset(ModePreset {
+                preset,
+                mode: self.mode_lens.get(cx),
+            }),
+        };
+
+        let result = file::format_clip(&clip)
+            .map_err(|err| err.to_string())
+            .and_then(|text| cx.set_clipboard(text).map_err(|err| err.to_string()));
+        if let Err(err) = result {
+            nih_error!("Could not copy: {err}");
+        }
+    }
+
+    /// Pastes from the clipboard. A whole preset replaces the selected one if
+    /// it's of the same mode, while nodes replace the part of the curve they
+    /// span. If a range is selected, the nodes are moved to start where it
+    /// does.
+    fn paste(&self, cx: &mut EventContext) {
+        let (Some(mut preset), Ok(text)) = (self.preset(cx), cx.get_clipboard()) else {
+            return;
+        };
+
+        match file::parse_clip(&text) {
+            Ok(Clip::Preset(pasted)) => {
+                // A beat curve means nothing as a volume curve and vice versa.
+                if pasted.mode == self.mode_lens.get(cx) {
+                    self.set_preset(cx, pasted.preset);
+                } else {
+                    nih_error!("Could not paste: the preset is for the other bank");
+                }
+            }
+
+            Ok(Clip::Nodes(mut nodes)) => {
+                if let Some((start, _)) = self.selected_range() {
+                    let first = nodes
+                        .iter()
+                        .map(|node| node.x)
+                        .fold(f32::INFINITY, f32::min);
+                    for node in &mut nodes {
+                        node.x += start - first;
+                    }
+                }
+
+                match preset.curve.splice(&nodes) {
+                    Ok(curve) => {
+                        preset.curve = curve;
+                        self.set_preset(cx, preset);
+                    }
+                    Err(err) => nih_error!("Could not paste: {err}"),
+                }
+            }
 
-            Mode::Vol => self
-                .vol_lens
-                .clone()
-                .index(self.index_lens.get(cx))
-                .then(Preset::curve)
-                .get(cx),
+            Err(err) => nih_error!("Could not paste: {err}"),
+        }
+    }
+
+    /// Snaps a horizontal position to the grid, unless shift is held.
+    fn snap_x(&self, cx: &EventContext, x: f32) -> f32 {
+        let length = self
+            .preset(cx)
+            .map_or(Length::default(), |preset| preset.length);
+
+        if cx.modifiers().shift() {
+            x
+        } else {
+            self.snap_lens.get(cx).snap(x, length)
         }
     }
 }
 
-pub fn draw_node(bounds: BoundingBox, canvas: &mut Canvas, node: Node) {
+/// Converts a position in the view to coordinates in the unit square.
+fn to_unit(bounds: BoundingBox, x: f32, y: f32) -> (f32, f32) {
+    let x = (x - bounds.x) / bounds.w;
+    let y = 1.0 - (y - bounds.y) / bounds.h;
+    (x.clamp(0.0, 1.0), y.clamp(0.0, 1.0))
+}
+
+/// Converts coordinates in the unit square to a position in the view.
+fn from_unit(bounds: BoundingBox, x: f32, y: f32) -> (f32, f32) {
     // We draw rightwards and upwards.
-    let x = bounds.x + bounds.w * node.x;
-    let y = bounds.y + bounds.h * (1.0 - node.y);
+    (bounds.x + bounds.w * x, bounds.y + bounds.h * (1.0 - y))
+}
+
+/// Whether a point in the view is within grabbing distance of another.
+fn hits(point: (f32, f32), x: f32, y: f32) -> bool {
+    (point.0 - x).hypot(point.1 - y) <= HIT_RADIUS
+}
+
+/// The index of the node at a position in the view, if any.
+fn node_at(bounds: BoundingBox, nodes: &[Node], x: f32, y: f32) -> Option<usize> {
+    nodes
+        .iter()
+        .position(|node| hits(from_unit(bounds, node.x, node.y), x, y))
+}
+
+/// The position of the tension handle of the segment starting at a node, in
+/// unit coordinates. The last node has no handle.
+fn handle(preset: &Preset, index: usize) -> Option<(f32, f32)> {
+    let a = preset.curve.nodes().get(index)?;
+    let b = preset.curve.nodes().get(index + 1)?;
+    let x = (a.x + b.x) / 2.0;
+    Some((x, preset.curve.value_at(x)))
+}
+
+/// The index of the node whose tension handle is at a position in the view,
+/// if any.
+fn handle_at(bounds: BoundingBox, preset: &Preset, x: f32, y: f32) -> Option<usize> {
+    (0..preset.curve.nodes().len()).find(|&index| {
+        handle(preset, index).map_or(false, |(hx, hy)| hits(from_unit(bounds, hx, hy), x, y))
+    })
+}
+
+/// Draws the grid behind the curve of a given length: a line for every grid
+/// cell, a stronger one for every beat, and horizontal lines at every quarter.
+/// Vertical lines that would be too close together are left out.
+fn draw_grid(bounds: BoundingBox, canvas: &mut Canvas, snap: Snap, length: Length) {
+    let vertical_lines = |path: &mut vg::Path, width: f32| {
+        if width * bounds.w < MIN_GRID_SPACING {
+            return;
+        }
+
+        let mut x = width;
+        while x < 1.0 - width / 2.0 {
+            let (left, _) = from_unit(bounds, x, 0.0);
+            path.move_to(left, bounds.y);
+            path.line_to(left, bounds.y + bounds.h);
+            x += width;
+        }
+    };
+
+    let mut cells = vg::Path::new();
+    if let Some(cell) = snap.cell(length) {
+        vertical_lines(&mut cells, cell);
+    }
+    canvas.stroke_path(&mut cells, &vg::Paint::color(GRID_COLOR));
+
+    let mut beats = vg::Path::new();
+    vertical_lines(&mut beats, (1.0 / length.beats(EDITOR_BAR_BEATS)) as f32);
+    for i in 1..GRID_ROWS {
+        let (_, y) = from_unit(bounds, 0.0, i as f32 / GRID_ROWS as f32);
+        beats.move_to(bounds.x, y);
+        beats.line_to(bounds.x + bounds.w, y);
+    }
+    canvas.stroke_path(&mut beats, &vg::Paint::color(BEAT_COLOR));
+}
+
+/// Draws the curve of a preset, sampling it at every pixel. Volume curves get
+/// the area under them filled in, while beat curves are drawn next to the
+/// identity line, which would play the audio back untouched.
+fn draw_curve(bounds: BoundingBox, canvas: &mut Canvas, preset: &Preset, mode: Mode) {
+    let columns = bounds.w.max(1.0) as usize;
+    let mut path = vg::Path::new();
+    for column in 0..=columns {
+        let t = column as f32 / columns as f32;
+        let (x, y) = from_unit(bounds, t, preset.curve.value_at(t));
+        if column == 0 {
+            path.move_to(x, y);
+        } else {
+            path.line_to(x, y);
+        }
+    }
+
+    match mode {
+        Mode::Beat => {
+            let mut identity = vg::Path::new();
+            identity.move_to(bounds.x, bounds.y + bounds.h);
+            identity.line_to(bounds.x + bounds.w, bounds.y);
+            canvas.stroke_path(&mut identity, &vg::Paint::color(BEAT_COLOR));
+        }
+
+        Mode::Vol => {
+            let mut area = path.clone();
+            area.line_to(bounds.x + bounds.w, bounds.y + bounds.h);
+            area.line_to(bounds.x, bounds.y + bounds.h);
+            area.close();
+            canvas.fill_path(&mut area, &vg::Paint::color(FILL_COLOR));
+        }
+    }
+
+    let mut paint = vg::Paint::color(CURVE_COLOR);
+    paint.set_line_width(2.0);
+    canvas.stroke_path(&mut path, &paint);
+}
+
+/// Shades the selected range.
+fn draw_selection(bounds: BoundingBox, canvas: &mut Canvas, start: f32, end: f32) {
+    let (left, _) = from_unit(bounds, start, 0.0);
+    let (right, _) = from_unit(bounds, end, 0.0);
+    let mut path = vg::Path::new();
+    path.rect(left, bounds.y, (right - left).max(1.0), bounds.h);
+    canvas.fill_path(&mut path, &vg::Paint::color(SELECTION_COLOR));
+}
+
+/// Draws a vertical line where the audio thread currently is.
+fn draw_playhead(bounds: BoundingBox, canvas: &mut Canvas, playhead: f32) {
+    if !(0.0..=1.0).contains(&playhead) {
+        return;
+    }
+
+    let (x, _) = from_unit(bounds, playhead, 0.0);
+    let mut path = vg::Path::new();
+    path.move_to(x, bounds.y);
+    path.line_to(x, bounds.y + bounds.h);
+
+    let mut paint = vg::Paint::color(PLAYHEAD_COLOR);
+    paint.set_line_width(2.0);
+    canvas.stroke_path(&mut path, &paint);
+}
+
+pub fn draw_node(bounds: BoundingBox, canvas: &mut Canvas, node: Node) {
+    let (x, y) = from_unit(bounds, node.x, node.y);
+    let mut path = vg::Path::new();
+    let half = NODE_SIZE / 2.0;
+    path.rect(x - half, y - half, NODE_SIZE, NODE_SIZE);
+    canvas.fill_path(&mut path, &vg::Paint::color(vg::Color::black()));
+}
 
-    canvas.clear_rect(x as u32 - 5, y as u32 - 5, 10, 10, vg::Color::black());
+/// Draws the tension handle of a segment.
+fn draw_handle(bounds: BoundingBox, canvas: &mut Canvas, (x, y): (f32, f32)) {
+    let (x, y) = from_unit(bounds, x, y);
+    let mut path = vg::Path::new();
+    path.circle(x, y, 4.0);
+    canvas.stroke_path(&mut path, &vg::Paint::color(vg::Color::black()));
 }
 
-impl<LBeat, LVol, LMode, LIndex> View for CurveView<LBeat, LVol, LMode, LIndex>
+impl<LBeat, LVol, LMode, LIndex, LSnap, LPlayhead> View
+    for CurveView<LBeat, LVol, LMode, LIndex, LSnap, LPlayhead>
 where
     LBeat: Lens<Target = Vec<Preset>>,
     LVol: Lens<Target = Vec<Preset>>,
     LMode: Lens<Target = Mode>,
     LIndex: Lens<Target = usize>,
+    LSnap: Lens<Target = Snap>,
+    LPlayhead: Lens<Target = Playhead>,
 {
     fn element(&self) -> Option<&'static str> {
-        Some("functor-list")
+        Some("curve-view")
+    }
+
+    fn event(&mut self, cx: &mut EventContext, event: &mut Event) {
+        event.map(|window_event, meta| match window_event {
+            WindowEvent::MouseDown(MouseButton::Left) => {
+                cx.capture();
+                cx.focus();
+                self.grab(cx);
+                meta.consume();
+            }
+
+            WindowEvent::MouseMove(_, _) => {
+                if self.drag.is_some() {
+                    self.drag(cx);
+                }
+            }
+
+            WindowEvent::MouseUp(MouseButton::Left) => {
+                cx.release();
+                self.drag = None;
+                cx.emit(UndoEvent::Checkpoint);
+            }
+
+            WindowEvent::MouseDown(MouseButton::Right)
+            | WindowEvent::MouseDoubleClick(MouseButton::Left) => {
+                self.delete(cx);
+                cx.emit(UndoEvent::Checkpoint);
+                meta.consume();
+            }
+
+            WindowEvent::KeyDown(Code::KeyC, _) if cx.modifiers().ctrl() => {
+                self.copy(cx);
+                meta.consume();
+            }
+
+            WindowEvent::KeyDown(Code::KeyV, _) if cx.modifiers().ctrl() => {
+                self.paste(cx);
+                cx.emit(UndoEvent::Checkpoint);
+                meta.consume();
+            }
+
+            _ => {}
+        });
     }
 
     fn draw(&self, cx: &mut DrawContext, canvas: &mut Canvas) {
         let bounds = cx.bounds();
+        let preset = self.preset(cx);
+        let length = preset
+            .as_ref()
+            .map_or(Length::default(), |preset| preset.length);
+        draw_grid(bounds, canvas, self.snap_lens.get(cx), length);
+
+        if let Some((start, end)) = self.selected_range() {
+            draw_selection(bounds, canvas, start, end);
+        }
+
+        if let Some(preset) = preset {
+            let mode = self.mode_lens.get(cx);
+            let playhead = self.playhead_lens.get(cx);
+            draw_curve(bounds, canvas, &preset, mode);
+            draw_playhead(
+                bounds,
+                canvas,
+                match mode {
+                    Mode::Beat => playhead.beat,
+                    Mode::Vol => playhead.vol,
+                },
+            );
+
+            for &node in preset.curve.iter() {
+                draw_node(bounds, canvas, node);
+            }
 
-        for &node in self.curve(cx).iter() {
-            draw_node(bounds, canvas, node);
+            if preset.curve.interpolation != Interpolation::Drop {
+                for index in 0..preset.curve.nodes().len() {
+                    if let Some(handle) = handle(&preset, index) {
+                        draw_handle(bounds, canvas, handle);
+                    }
+                }
+            }
         }
 
         let mut path = vg::Path::new();
@@ -1,26 +1,27 @@
 use std::marker::PhantomData;
 
-use crate::curve::{Curve, Mode, Node, Preset};
+use crate::curve::{Mode, ModePreset, Preset};
+use crate::factory;
+use crate::library::{self, LibraryPreset};
 use nih_plug_vizia::vizia::prelude::*;
 
-use super::PresetEvent;
-
-/// Some dummy presets to test the layout.
-pub(crate) fn test_presets(n: usize) -> Vec<Preset> {
-    (0..n)
-        .map(|i| Preset {
-            name: format!("Test {}", i),
-            curve: Curve {
-                nodes: vec![
-                    Node { x: 0.0, y: 0.0 },
-                    Node {
-                        x: i as f32 / 50.0,
-                        y: i as f32 / 50.0,
-                    },
-                ],
-            },
-        })
-        .collect()
+use super::{AppData, AppEvent, PresetEvent};
+
+/// The name shown for the bank in the category browser.
+const BANK_NAME: &str = "Bank";
+
+/// The name shown for the library presets that aren't in any category.
+const NO_CATEGORY_NAME: &str = "Other";
+
+/// What a list is showing, and what it's filtered by.
+#[derive(Clone, Default, Data, Lens)]
+pub struct Browse {
+    /// The category being browsed. Zero is the bank, and anything higher is a
+    /// category of the library, in the order of [`library::categories`].
+    pub category: usize,
+
+    /// Only presets whose name contains this are shown.
+    pub search: String,
 }
 
 /// The buttons for selecting a preset.
@@ -46,31 +47,113 @@ impl<L: 'static + Lens<Target = Vec<Preset>>> View for FunctorButton<L> {
     }
 }
 
+/// A button for selecting a preset in the bank. Right-clicking it opens a menu
+/// for renaming it and for adding, copying, moving and deleting presets.
 pub fn functor_button<L: Lens<Target = Vec<Preset>>>(
     cx: &mut Context,
     mode: Mode,
     lens: L,
     index: usize,
-) -> Handle<Button>
+) -> Handle<ZStack>
 where
     <L as Lens>::Source: Model,
 {
-    let ptr = lens.clone().index(index);
+    let ptr = lens.index(index);
+    let menu_open = AppData::context_menu.map(move |menu| *menu == Some((mode, index)));
+
+    ZStack::new(cx, move |cx| {
+        let name = ptr.clone().then(Preset::name);
+        Button::new(
+            cx,
+            move |cx| {
+                cx.emit(PresetEvent::Select { mode, index });
+            },
+            |cx| Label::new(cx, name).width(Pixels(58.0)).font_size(12.0),
+        )
+        .on_mouse_down(move |cx, button| {
+            if button == MouseButton::Right {
+                cx.emit(AppEvent::ContextMenu(Some((mode, index))));
+            }
+        });
+
+        Popup::new(cx, menu_open, true, move |cx| {
+            // Renaming on every key press would rebuild the bank, and this menu
+            // along with it, so the name is only set once it's submitted.
+            Textbox::new(cx, ptr.then(Preset::name))
+                .on_submit(move |cx, name, _| {
+                    cx.emit(PresetEvent::Rename { mode, index, name });
+                })
+                .font_size(12.0)
+                .width(Stretch(1.0));
+
+            context_item(cx, "New", move || PresetEvent::Insert {
+                mode,
+                index: index + 1,
+                preset: factory::init_preset(mode),
+            });
+            context_item(cx, "Duplicate", move || PresetEvent::Duplicate {
+                mode,
+                index,
+            });
+            context_item(cx, "Move left", move || PresetEvent::Move {
+                mode,
+                from: index,
+                to: index.saturating_sub(1),
+            });
+            context_item(cx, "Move right", move || PresetEvent::Move {
+                mode,
+                from: index,
+                to: index + 1,
+            });
+            context_item(cx, "Delete", move || PresetEvent::Delete { mode, index });
+        })
+        .on_blur(|cx| cx.emit(AppEvent::ContextMenu(None)))
+        .top(Percentage(100.0))
+        .width(Pixels(120.0));
+    })
+    .size(Auto)
+}
 
+/// An entry in the context menu of a bank button.
+fn context_item(cx: &mut Context, text: &'static str, event: impl 'static + Fn() -> PresetEvent) {
     Button::new(
         cx,
         move |cx| {
-            cx.emit(PresetEvent::Select { mode, index });
+            cx.emit(event());
+            cx.emit(AppEvent::ContextMenu(None));
         },
-        |cx| {
-            Label::new(cx, ptr.then(Preset::name))
-                .width(Pixels(58.0))
-                .font_size(12.0)
+        move |cx| Label::new(cx, text).font_size(12.0),
+    )
+    .width(Stretch(1.0));
+}
+
+/// A button for loading a preset from the library into the selected slot.
+fn library_button(cx: &mut Context, preset: ModePreset) -> Handle<Button> {
+    let name = preset.preset.name.clone();
+
+    Button::new(
+        cx,
+        move |cx| {
+            cx.emit(PresetEvent::Load {
+                preset: preset.clone(),
+            });
         },
+        move |cx| Label::new(cx, &name).width(Pixels(58.0)).font_size(12.0),
     )
 }
 
-/// A list widget for the beats or volumes.
+/// An entry in a list.
+#[derive(Clone)]
+enum Item {
+    /// A preset in the bank, by index.
+    Bank(usize),
+
+    /// A preset in the library.
+    Library(ModePreset),
+}
+
+/// A list widget for the beats or volumes, which can browse both the bank and
+/// the categories of the library.
 pub struct FunctorList<L>
 where
     L: Lens<Target = Vec<Preset>>,
@@ -94,40 +177,142 @@ impl<L: 'static + Lens<Target = Vec<Preset>>> View for FunctorList<L> {
 }
 
 impl<L: 'static + Lens<Target = Vec<Preset>>> FunctorList<L> {
-    /// Creates a new [`FunctorList] view with a binding to the given lens and a
-    /// template for constructing the list items.
-    pub fn new(cx: &mut Context, mode: Mode, lens: L) -> Handle<Self>
+    /// Creates a new [`FunctorList`] view with a binding to the given bank, the
+    /// presets in the library, and what's being browsed.
+    pub fn new<LLibrary, LBrowse>(
+        cx: &mut Context,
+        mode: Mode,
+        lens: L,
+        library: LLibrary,
+        browse: LBrowse,
+    ) -> Handle<Self>
     where
         <L as Lens>::Source: Model,
+        LLibrary: 'static + Lens<Target = Vec<LibraryPreset>>,
+        <LLibrary as Lens>::Source: Model,
+        LBrowse: 'static + Lens<Target = Browse>,
     {
         FunctorList::default().build(cx, move |cx| {
-            // Bind to the list length.
-            Binding::new(
-                cx,
-                lens.clone().map(|lst| lst.len()),
-                move |cx, list_len| {
-                    // If the number of list items is different to the number of
-                    // children of the ListView then remove and rebuild all the
-                    // children.
-                    let list_len = list_len.get_fallible(cx).unwrap_or_default();
-
-                    for row in 0..(list_len / 3) {
-                        HStack::new(cx, |cx| {
-                            for col in 0..3 {
-                                functor_button(cx, mode, lens.clone(), 3 * row + col);
-                            }
-                        })
-                        .max_height(Pixels(35.0));
-                    }
+            // Everything below depends on the library, which changes whenever a
+            // scan of it finishes.
+            Binding::new(cx, library, move |cx, library| {
+                let library = library.get(cx);
+                let categories = library::categories(&library, mode);
+                let num_categories = categories.len() + 1;
+                let (lens, browse) = (lens.clone(), browse.clone());
+
+                HStack::new(cx, |cx| {
+                    Button::new(
+                        cx,
+                        move |cx| cx.emit(AppEvent::Browse { mode, step: -1 }),
+                        |cx| Label::new(cx, "<").font_size(12.0),
+                    );
+
+                    let names = categories.clone();
+                    Label::new(
+                        cx,
+                        browse
+                            .clone()
+                            .then(Browse::category)
+                            .map(move |category| category_name(&names, *category)),
+                    )
+                    .font_size(12.0)
+                    .width(Pixels(70.0))
+                    .left(Pixels(4.0))
+                    .right(Pixels(4.0));
+
+                    Button::new(
+                        cx,
+                        move |cx| cx.emit(AppEvent::Browse { mode, step: 1 }),
+                        |cx| Label::new(cx, ">").font_size(12.0),
+                    )
+                    .right(Pixels(6.0));
 
-                    HStack::new(cx, |cx| {
-                        for col in 0..(list_len % 3) {
-                            functor_button(cx, mode, lens.clone(), 3 * (list_len / 3) + col);
+                    Textbox::new(cx, browse.clone().then(Browse::search))
+                        .on_edit(move |cx, text| cx.emit(AppEvent::Search { mode, text }))
+                        .font_size(12.0)
+                        .width(Stretch(1.0));
+                })
+                .height(Pixels(24.0))
+                .bottom(Pixels(4.0));
+
+                ScrollView::new(cx, 0.0, 0.0, false, true, move |cx| {
+                    Binding::new(cx, browse.clone(), move |cx, browse| {
+                        let Browse { category, search } = browse.get(cx);
+                        let category = category % num_categories;
+                        let lens = lens.clone();
+
+                        if category == 0 {
+                            // Only rebuild the bank when a name changes, rather
+                            // than on every edit of a curve.
+                            let names = lens.clone().map(|presets| {
+                                presets
+                                    .iter()
+                                    .map(|preset| preset.name.clone())
+                                    .collect::<Vec<_>>()
+                            });
+
+                            Binding::new(cx, names, move |cx, names| {
+                                let search = search.trim().to_lowercase();
+                                let items = names
+                                    .get(cx)
+                                    .iter()
+                                    .enumerate()
+                                    .filter(|(_, name)| name.to_lowercase().contains(&search))
+                                    .map(|(index, _)| Item::Bank(index))
+                                    .collect();
+                                grid(cx, mode, lens.clone(), items);
+                            });
+                        } else {
+                            let category = &categories[category - 1];
+                            let items = library
+                                .iter()
+                                .filter(|preset| {
+                                    preset.preset.mode == mode
+                                        && &preset.category == category
+                                        && preset.matches(&search)
+                                })
+                                .map(|preset| Item::Library(preset.preset.clone()))
+                                .collect();
+                            grid(cx, mode, lens, items);
                         }
-                    })
-                    .max_height(Pixels(35.0));
-                },
-            );
+                    });
+                });
+            });
+        })
+    }
+}
+
+/// The name of a category in the browser, given the library's categories for
+/// the list.
+fn category_name(categories: &[Option<String>], category: usize) -> String {
+    match category % (categories.len() + 1) {
+        0 => BANK_NAME.to_owned(),
+        category => categories[category - 1]
+            .clone()
+            .unwrap_or_else(|| NO_CATEGORY_NAME.to_owned()),
+    }
+}
+
+/// Lays out the buttons for a list of items, three to a row.
+fn grid<L>(cx: &mut Context, mode: Mode, lens: L, items: Vec<Item>)
+where
+    L: 'static + Lens<Target = Vec<Preset>>,
+    <L as Lens>::Source: Model,
+{
+    for row in items.chunks(3) {
+        HStack::new(cx, |cx| {
+            for item in row {
+                match item {
+                    Item::Bank(index) => {
+                        functor_button(cx, mode, lens.clone(), *index);
+                    }
+                    Item::Library(preset) => {
+                        library_button(cx, preset.clone());
+                    }
+                }
+            }
         })
+        .max_height(Pixels(35.0));
     }
 }
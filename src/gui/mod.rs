@@ -1,38 +1,103 @@
 pub mod curve_view;
 pub mod icon;
 pub mod list;
+pub mod undo;
 
-use nih_plug::prelude::Plugin;
+use nih_plug::nih_error;
+use nih_plug::prelude::{AsyncExecutor, GuiContext, ParamSetter, Plugin};
 use nih_plug_vizia::vizia::prelude::*;
+use nih_plug_vizia::widgets::ParamSlider;
 use nih_plug_vizia::{assets, create_vizia_editor, ViziaState, ViziaTheming};
 
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use crate::curve::{Mode, Preset};
-use crate::gui::list::{test_presets, FunctorList};
-use crate::FunctorParams;
+use crate::curve::{
+    Curve, CurveError, Interpolation, Length, LengthUnit, Mode, ModePreset, Preset,
+};
+use crate::dsp::scratch::ScratchControl;
+use crate::file::{self, Bank, FileError};
+use crate::generate::LfoShape;
+use crate::gui::curve_view::{CurveView, Playhead, Snap};
+use crate::gui::list::{Browse, FunctorList};
+use crate::gui::undo::{Edit, History, Snapshot, UndoEvent};
+use crate::library::{self, Library, LibraryPreset};
+use crate::{Functor, FunctorParams, Playheads, Task, MAX_PRESETS};
 
 /// The size of the window.
-const SIZE: (u32, u32) = (800, 610);
+const SIZE: (u32, u32) = (800, 760);
+
+/// How often the editor picks up what's changed behind its back, like the
+/// playheads, any learned scratch controller, and the library.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(30);
+
+/// How far the smooth transform pulls nodes towards their neighbours.
+const SMOOTH_AMOUNT: f32 = 0.5;
+
+/// How far nodes may lie from the simplified curve.
+const SIMPLIFY_TOLERANCE: f32 = 0.02;
+
+/// How many cycles per bar generated LFOs have, and how many steps generated
+/// envelopes have.
+const GENERATE_BEATS: u32 = 4;
 
 /// All the data that needs to be kept track of in the editor.
 #[derive(Lens)]
 struct AppData {
     /// The parameters for the plugin.
     params: Arc<FunctorParams>,
+    /// Lets the editor change parameters the way the host expects.
+    gui_context: Arc<dyn GuiContext>,
+    /// Where the audio thread is within the active curves. This is polled on
+    /// every refresh, since nothing tells the editor when it changes.
+    playheads: Arc<Playheads>,
+    /// The playheads as of the last refresh.
+    playhead: Playhead,
+    /// The name of the scratch controller as of the last refresh, or an
+    /// ellipsis while waiting to learn one.
+    scratch: String,
+    /// Runs tasks off of the GUI thread.
+    async_executor: AsyncExecutor<Functor>,
 
     /// The mode of the selected preset.
     mode: Mode,
     /// The index of the selected preset.
     index: usize,
+    /// The bank button whose context menu is open, if any.
+    context_menu: Option<(Mode, usize)>,
 
-    /// The presets for the beats.
+    /// The presets for the beats. This is a copy of the bank in the parameters,
+    /// which is updated on every change.
     beat_presets: Vec<Preset>,
-    /// The presets for the volumes.
+    /// The presets for the volumes. This is a copy of the bank in the
+    /// parameters, which is updated on every change.
     vol_presets: Vec<Preset>,
 
+    /// The presets in the user's library, which are scanned in the background.
+    shared_library: Arc<Library>,
+    /// The scan the library is a copy of.
+    library_generation: u32,
+    /// A copy of the presets in the user's library as of the last finished
+    /// scan.
+    library: Vec<LibraryPreset>,
+    /// What the beat list is showing.
+    beat_browse: Browse,
+    /// What the volume list is showing.
+    vol_browse: Browse,
+
+    /// The changes to the banks that can be undone or redone.
+    history: History,
+
     dc: bool,
-    // interpolation: Interpolation,
+    /// The name of the selected preset.
+    name: String,
+    /// The interpolation of the selected preset.
+    interpolation: Interpolation,
+    /// The length of the selected preset.
+    length: Length,
+    /// The grid nodes snap to while editing.
+    snap: Snap,
 }
 
 /// An event relating to a preset.
@@ -54,27 +119,626 @@ pub enum PresetEvent {
         /// The index of the beat.
         index: usize,
     },
+
+    /// Load a preset from the library into the selected slot of its mode.
+    Load {
+        /// The preset and its mode.
+        preset: ModePreset,
+    },
+
+    /// Rename a beat or volume.
+    Rename {
+        /// Whether we're renaming a beat or a volume.
+        mode: Mode,
+        /// The index of the beat or volume.
+        index: usize,
+        /// The new name.
+        name: String,
+    },
+
+    /// Insert a copy of a beat or volume right after it, and select the copy.
+    Duplicate {
+        /// Whether we're duplicating a beat or a volume.
+        mode: Mode,
+        /// The index of the beat or volume.
+        index: usize,
+    },
+
+    /// Move a beat or volume to another place in its bank.
+    Move {
+        /// Whether we're moving a beat or a volume.
+        mode: Mode,
+        /// The index of the beat or volume.
+        from: usize,
+        /// The index it ends up at.
+        to: usize,
+    },
+
+    /// Remove a beat or volume from its bank.
+    Delete {
+        /// Whether we're deleting a beat or a volume.
+        mode: Mode,
+        /// The index of the beat or volume.
+        index: usize,
+    },
+
+    /// Add a new beat or volume, and select it.
+    Insert {
+        /// Whether we're adding a beat or a volume.
+        mode: Mode,
+        /// The index the new preset ends up at. Anything past the end of the
+        /// bank adds it at the end.
+        index: usize,
+        /// The new preset.
+        preset: Preset,
+    },
 }
 
-/*
+/// An operation on the curve of the selected preset.
+#[derive(Clone, Copy)]
+pub enum Transform {
+    /// Play the curve back to front.
+    Flip,
+    /// Turn the curve upside down.
+    Invert,
+    /// Move the curve a grid cell to the left, wrapping around.
+    ShiftLeft,
+    /// Move the curve a grid cell to the right, wrapping around.
+    ShiftRight,
+    /// Halve the values, or the depth of a volume curve.
+    Halve,
+    /// Double the values, or the depth of a volume curve.
+    Double,
+    /// Fit the curve into the bar a number of times.
+    Repeat(usize),
+    /// Move the nodes onto the grid.
+    Quantize,
+    /// Even out the values of the nodes.
+    Smooth,
+    /// Remove the nodes that barely change the curve.
+    Simplify,
+}
 
-#[derive(Clone, Copy, PartialEq, Eq)]
-pub enum Interpolation {
-    Drop,
-    Linear,
-    Cubic,
-    Hermite,
+/// A kind of curve to generate for the selected preset.
+#[derive(Clone, Copy)]
+pub enum Generator {
+    /// An LFO with a cycle per beat.
+    Lfo(LfoShape),
+    /// A euclidean gate rhythm.
+    Euclidean {
+        /// How many steps are open.
+        pulses: u32,
+        /// How many steps there are.
+        steps: u32,
+    },
+    /// A new random pattern with a step per grid cell.
+    Random,
+    /// An envelope on every beat.
+    Envelope,
 }
 
-pub enum AppEvent {
-    DcToggle,
+impl Generator {
+    /// Whether the generator has a shape for a mode. Only random steps have a
+    /// shape for beats.
+    fn fits(self, mode: Mode) -> bool {
+        matches!((self, mode), (Self::Random, _) | (_, Mode::Vol))
+    }
+}
 
+/// An event relating to the editor settings.
+pub enum AppEvent {
+    // DcToggle,
+    /// Set the interpolation of the selected preset.
     Interpolation(Interpolation),
+
+    /// Lengthen or shorten the selected preset by a number of units.
+    LengthCount(i32),
+
+    /// Set the unit the length of the selected preset is counted in.
+    LengthUnit(LengthUnit),
+
+    /// Set the grid nodes snap to while editing.
+    Snap(Snap),
+
+    /// Open the context menu of a bank button, or close it.
+    ContextMenu(Option<(Mode, usize)>),
+
+    /// Make the next controller to move the scratch control, or stop waiting
+    /// for one.
+    LearnScratch,
+
+    /// Turn scratching off.
+    ClearScratch,
+
+    /// Pick up the changes made by the audio thread and the library scan since
+    /// the last refresh.
+    Refresh,
+
+    /// Load a preset from a file into the selected slot of its mode.
+    ImportPreset,
+
+    /// Save the selected preset to a file.
+    ExportPreset,
+
+    /// Replace both banks with ones loaded from a file.
+    ImportBank,
+
+    /// Save both banks to a file.
+    ExportBank,
+
+    /// Transform the curve of the selected preset.
+    Transform(Transform),
+
+    /// Replace the curve of the selected preset by a generated one.
+    Generate(Generator),
+
+    /// Move to the next or previous category in one of the lists.
+    Browse {
+        /// The list to browse.
+        mode: Mode,
+        /// How many categories to move forwards, or backwards if negative.
+        step: isize,
+    },
+
+    /// Filter one of the lists by name.
+    Search {
+        /// The list to filter.
+        mode: Mode,
+        /// The text names need to contain.
+        text: String,
+    },
+}
+
+/// A file dialog for presets or banks, which starts out in the library if
+/// there is one.
+fn file_dialog(title: &str) -> rfd::FileDialog {
+    let dialog = rfd::FileDialog::new()
+        .set_title(title)
+        .add_filter("Functor presets", &[file::EXTENSION]);
+
+    match library::library_dir().filter(|dir| dir.is_dir()) {
+        Some(dir) => dialog.set_directory(dir),
+        None => dialog,
+    }
+}
+
+impl AppData {
+    /// The selected preset, if any.
+    fn selected_preset(&mut self) -> Option<&mut Preset> {
+        match self.mode {
+            Mode::Beat => self.beat_presets.get_mut(self.index),
+            Mode::Vol => self.vol_presets.get_mut(self.index),
+        }
+    }
+
+    /// The bank for a mode.
+    fn bank(&mut self, mode: Mode) -> &mut Vec<Preset> {
+        match mode {
+            Mode::Beat => &mut self.beat_presets,
+            Mode::Vol => &mut self.vol_presets,
+        }
+    }
+
+    /// The current state of the banks, for the undo history.
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            beat_presets: self.beat_presets.clone(),
+            vol_presets: self.vol_presets.clone(),
+            mode: self.mode,
+            index: self.index,
+            beat_slot: self.slot(Mode::Beat),
+            vol_slot: self.slot(Mode::Vol),
+        }
+    }
+
+    /// Records the current state in the undo history, right before a change.
+    fn record(&mut self, edit: Option<Edit>) {
+        let snapshot = self.snapshot();
+        self.history.record(snapshot, edit);
+    }
+
+    /// Goes back to a state from the undo history.
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.beat_presets = snapshot.beat_presets;
+        self.vol_presets = snapshot.vol_presets;
+        self.mode = snapshot.mode;
+        self.index = snapshot.index;
+        self.set_slot(Mode::Beat, snapshot.beat_slot);
+        self.set_slot(Mode::Vol, snapshot.vol_slot);
+
+        self.store(Mode::Beat);
+        self.store(Mode::Vol);
+        self.sync_selected();
+    }
+
+    /// Writes a bank back to the parameters, so that it's saved, and has the
+    /// audio thread pick up the changes.
+    fn store(&self, mode: Mode) {
+        match mode {
+            Mode::Beat => *self.params.beat_presets.write().unwrap() = self.beat_presets.clone(),
+            Mode::Vol => *self.params.vol_presets.write().unwrap() = self.vol_presets.clone(),
+        }
+
+        self.async_executor.execute_background(Task::RebuildTables);
+    }
+
+    /// The slot the parameter picking the presets of a mode points at.
+    fn slot(&self, mode: Mode) -> usize {
+        match mode {
+            Mode::Beat => self.params.beat.value() as usize,
+            Mode::Vol => self.params.vol.value() as usize,
+        }
+    }
+
+    /// Points the parameter picking the presets of a mode at a slot, telling
+    /// the host about it like any other change made from the editor.
+    fn set_slot(&self, mode: Mode, slot: usize) {
+        if slot == self.slot(mode) {
+            return;
+        }
+
+        let param = match mode {
+            Mode::Beat => &self.params.beat,
+            Mode::Vol => &self.params.vol,
+        };
+        let setter = ParamSetter::new(self.gui_context.as_ref());
+        setter.begin_set_parameter(param);
+        setter.set_parameter(param, slot as i32);
+        setter.end_set_parameter(param);
+    }
+
+    /// Keeps the selection and the parameter picking the presets of a mode on
+    /// the same presets after their bank is reordered, given the slot the
+    /// preset in every old slot ends up in.
+    fn follow_slots(&mut self, mode: Mode, new_slot: impl Fn(usize) -> usize) {
+        let last = self.bank(mode).len().saturating_sub(1);
+        if self.mode == mode {
+            self.index = new_slot(self.index).min(last);
+        }
+        self.set_slot(mode, new_slot(self.slot(mode)).min(last));
+    }
+
+    /// Asks for a preset file and loads it into the selected slot of its mode,
+    /// then selects it.
+    fn import_preset(&mut self) -> Result<(), FileError> {
+        let Some(path) = file_dialog("Load preset").pick_file() else {
+            return Ok(());
+        };
+
+        self.load(file::load_preset(&path)?);
+        Ok(())
+    }
+
+    /// Loads a preset into the selected slot of its mode, or at the end of the
+    /// bank if there's no such slot, then selects it. While the other mode is
+    /// shown, the slot its parameter points at counts as the selected one.
+    fn load(&mut self, ModePreset { preset, mode }: ModePreset) {
+        let index = if self.mode == mode {
+            self.index
+        } else {
+            self.slot(mode)
+        };
+        if index >= self.bank(mode).len() && self.bank(mode).len() >= MAX_PRESETS {
+            nih_error!("Could not load the preset: the bank is full");
+            return;
+        }
+
+        self.record(None);
+        let bank = self.bank(mode);
+
+        self.index = match bank.get_mut(index) {
+            Some(old_preset) => {
+                *old_preset = preset;
+                index
+            }
+            None => {
+                bank.push(preset);
+                bank.len() - 1
+            }
+        };
+        self.mode = mode;
+        self.set_slot(mode, self.index);
+
+        self.store(mode);
+        self.sync_selected();
+    }
+
+    /// Asks where to save the selected preset, and saves it there.
+    fn export_preset(&mut self) -> Result<(), FileError> {
+        let mode = self.mode;
+        let Some(preset) = self.selected_preset().cloned() else {
+            return Ok(());
+        };
+
+        let file_name = file::preset_file_name(&preset.name);
+        match file_dialog("Save preset")
+            .set_file_name(&file_name)
+            .save_file()
+        {
+            Some(path) => file::save_preset(&path, &ModePreset { preset, mode }),
+            None => Ok(()),
+        }
+    }
+
+    /// Asks for a bank file and replaces both banks with it. Presets past the
+    /// last slot the parameters can reach are left out.
+    fn import_bank(&mut self) -> Result<(), FileError> {
+        let Some(path) = file_dialog("Load bank").pick_file() else {
+            return Ok(());
+        };
+
+        let Bank { mut beat, mut vol } = file::load_bank(&path)?;
+        beat.truncate(MAX_PRESETS);
+        vol.truncate(MAX_PRESETS);
+
+        self.record(None);
+        self.beat_presets = beat;
+        self.vol_presets = vol;
+
+        self.store(Mode::Beat);
+        self.store(Mode::Vol);
+        self.sync_selected();
+        Ok(())
+    }
+
+    /// Asks where to save both banks, and saves them there.
+    fn export_bank(&self) -> Result<(), FileError> {
+        let bank = Bank {
+            beat: self.beat_presets.clone(),
+            vol: self.vol_presets.clone(),
+        };
+
+        let file_name = format!("Functor bank.{}", file::EXTENSION);
+        match file_dialog("Save bank")
+            .set_file_name(&file_name)
+            .save_file()
+        {
+            Some(path) => file::save_bank(&path, &bank),
+            None => Ok(()),
+        }
+    }
+
+    /// Transforms the curve of the selected preset.
+    fn transform(&mut self, transform: Transform) -> Result<(), CurveError> {
+        let mode = self.mode;
+        let snap = self.snap;
+        // Volume curves are scaled from the top, so that the gaps get deeper.
+        let pivot = match mode {
+            Mode::Beat => 0.0,
+            Mode::Vol => 1.0,
+        };
+
+        let Some(preset) = self.selected_preset() else {
+            return Ok(());
+        };
+
+        // Shifting and quantizing use the grid, or sixteenths if there's none.
+        let length = preset.length;
+        let cell = snap
+            .cell(length)
+            .or(Snap::Sixteenth.cell(length))
+            .unwrap_or_default();
+
+        let curve = &preset.curve;
+        let curve = match transform {
+            Transform::Flip => curve.flip(),
+            Transform::Invert => curve.invert(),
+            Transform::ShiftLeft => curve.shift(-cell)?,
+            Transform::ShiftRight => curve.shift(cell)?,
+            Transform::Halve => curve.scale_y(0.5, pivot),
+            Transform::Double => curve.scale_y(2.0, pivot),
+            Transform::Repeat(times) => curve.repeat(times, mode)?,
+            Transform::Quantize => curve.quantize(cell)?,
+            Transform::Smooth => curve.smooth(SMOOTH_AMOUNT),
+            Transform::Simplify => curve.simplify(SIMPLIFY_TOLERANCE),
+        };
+
+        self.record(None);
+        if let Some(preset) = self.selected_preset() {
+            preset.curve = curve;
+        }
+
+        self.store(mode);
+        self.sync_selected();
+        Ok(())
+    }
+
+    /// Replaces the curve of the selected preset by a generated one, if the
+    /// generator fits its mode.
+    fn generate(&mut self, generator: Generator) -> Result<(), CurveError> {
+        let mode = self.mode;
+        if !generator.fits(mode) {
+            return Ok(());
+        }
+
+        let curve = match generator {
+            Generator::Lfo(shape) => Curve::lfo(shape, GENERATE_BEATS, 0.0, 0.5)?,
+            Generator::Euclidean { pulses, steps } => Curve::euclidean(pulses, steps, 0, 0.5)?,
+            Generator::Random => {
+                let length = self
+                    .selected_preset()
+                    .map_or(Length::default(), |preset| preset.length);
+                let cell = self.snap.cell(length).or(Snap::Sixteenth.cell(length));
+                let steps = cell.map_or(16, |cell| (1.0 / cell).round().max(1.0) as u32);
+                let seed = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |time| time.as_nanos() as u64);
+                Curve::random_steps(steps, seed, mode)?
+            }
+            Generator::Envelope => Curve::envelope(GENERATE_BEATS, 0.05, 0.3, 0.6, 0.2)?,
+        };
+
+        if self.selected_preset().is_none() {
+            return Ok(());
+        }
+
+        self.record(None);
+        if let Some(preset) = self.selected_preset() {
+            preset.curve = curve;
+        }
+
+        self.store(mode);
+        self.sync_selected();
+        Ok(())
+    }
+
+    /// What one of the lists is showing.
+    fn browse(&mut self, mode: Mode) -> &mut Browse {
+        match mode {
+            Mode::Beat => &mut self.beat_browse,
+            Mode::Vol => &mut self.vol_browse,
+        }
+    }
+
+    /// Adds a preset to a bank and selects it, unless the bank is full. The
+    /// parameter picking the presets of the mode stays on the same preset.
+    fn insert(&mut self, mode: Mode, index: usize, preset: Preset) {
+        if self.bank(mode).len() >= MAX_PRESETS {
+            nih_error!("Could not add the preset: the bank is full");
+            return;
+        }
+
+        self.record(None);
+
+        let bank = self.bank(mode);
+        let index = index.min(bank.len());
+        bank.insert(index, preset);
+        self.follow_slots(mode, |slot| if slot >= index { slot + 1 } else { slot });
+
+        self.mode = mode;
+        self.index = index;
+        self.store(mode);
+        self.sync_selected();
+    }
+
+    /// Moves a preset within its bank, keeping the same presets selected and
+    /// picked by the parameter.
+    fn move_preset(&mut self, mode: Mode, from: usize, to: usize) {
+        let bank = self.bank(mode);
+        if from >= bank.len() || to >= bank.len() || from == to {
+            return;
+        }
+
+        self.record(None);
+        let bank = self.bank(mode);
+        let preset = bank.remove(from);
+        bank.insert(to, preset);
+
+        self.follow_slots(mode, |slot| {
+            if slot == from {
+                to
+            } else if from < slot && slot <= to {
+                slot - 1
+            } else if to <= slot && slot < from {
+                slot + 1
+            } else {
+                slot
+            }
+        });
+
+        self.store(mode);
+    }
+
+    /// Removes a preset from its bank. If the selection or the parameter was on
+    /// it, it moves to the preset taking its place.
+    fn delete(&mut self, mode: Mode, index: usize) {
+        let bank = self.bank(mode);
+        if index >= bank.len() {
+            return;
+        }
+
+        self.record(None);
+        self.bank(mode).remove(index);
+        self.follow_slots(mode, |slot| if slot > index { slot - 1 } else { slot });
+
+        self.store(mode);
+        self.sync_selected();
+    }
+
+    /// Sets the length of the selected preset.
+    fn set_length(&mut self, length: Length) {
+        if length == self.length || self.selected_preset().is_none() {
+            return;
+        }
+
+        self.record(None);
+        if let Some(preset) = self.selected_preset() {
+            preset.length = length;
+        }
+
+        self.store(self.mode);
+        self.sync_selected();
+    }
+
+    /// Updates the fields that mirror the selected preset.
+    fn sync_selected(&mut self) {
+        if let Some(preset) = self.selected_preset() {
+            let (name, interpolation) = (preset.name.clone(), preset.curve.interpolation);
+            let length = preset.length;
+            self.name = name;
+            self.interpolation = interpolation;
+            self.length = length;
+        } else {
+            self.name.clear();
+        }
+    }
+
+    /// Updates the fields that mirror the state of the audio thread and the
+    /// library scan.
+    fn refresh(&mut self) {
+        self.playhead = Playhead {
+            beat: self.playheads.beat.load(Ordering::Relaxed),
+            vol: self.playheads.vol.load(Ordering::Relaxed),
+        };
+
+        self.scratch = if self.params.scratch_learn.load(Ordering::Relaxed) {
+            String::from("...")
+        } else {
+            let raw = self.params.scratch_control.load(Ordering::Relaxed);
+            ScratchControl::from_raw(raw).name()
+        };
+
+        let generation = self.shared_library.generation();
+        if generation != self.library_generation {
+            self.library_generation = generation;
+            self.library = self.shared_library.presets();
+        }
+    }
 }
-*/
 
 impl Model for AppData {
-    fn event(&mut self, _: &mut EventContext, event: &mut Event) {
+    fn event(&mut self, cx: &mut EventContext, event: &mut Event) {
+        // Undo shortcuts
+        event.map(|window_event, meta| match window_event {
+            WindowEvent::KeyDown(Code::KeyZ, _) if cx.modifiers().ctrl() => {
+                if cx.modifiers().shift() {
+                    cx.emit(UndoEvent::Redo);
+                } else {
+                    cx.emit(UndoEvent::Undo);
+                }
+                meta.consume();
+            }
+
+            _ => {}
+        });
+
+        // Undo event
+        event.map(|undo_event, _| match undo_event {
+            UndoEvent::Undo => {
+                if let Some(snapshot) = self.history.undo(self.snapshot()) {
+                    self.restore(snapshot);
+                }
+            }
+
+            UndoEvent::Redo => {
+                if let Some(snapshot) = self.history.redo(self.snapshot()) {
+                    self.restore(snapshot);
+                }
+            }
+
+            UndoEvent::Checkpoint => self.history.checkpoint(),
+        });
+
         // Preset event
         event.map(|preset_event, _| match preset_event {
             PresetEvent::Set {
@@ -82,6 +746,11 @@ impl Model for AppData {
                 index,
                 preset,
             } => {
+                self.record(Some(Edit::Curve {
+                    mode: *mode,
+                    index: *index,
+                }));
+
                 let old_preset = match mode {
                     Mode::Beat => self.beat_presets.get_mut(*index),
                     Mode::Vol => self.vol_presets.get_mut(*index),
@@ -90,24 +759,147 @@ impl Model for AppData {
                 if let Some(old_preset) = old_preset {
                     *old_preset = preset.clone();
                 }
+
+                self.store(*mode);
+                self.sync_selected();
             }
 
             PresetEvent::Select { mode, index } => {
                 self.mode = *mode;
                 self.index = *index;
+                self.set_slot(*mode, *index);
+                self.sync_selected();
+            }
+
+            PresetEvent::Load { preset } => self.load(preset.clone()),
+
+            PresetEvent::Rename { mode, index, name } => {
+                self.record(None);
+
+                if let Some(preset) = self.bank(*mode).get_mut(*index) {
+                    preset.name = name.clone();
+                }
+
+                self.store(*mode);
+                self.sync_selected();
             }
+
+            PresetEvent::Duplicate { mode, index } => {
+                if let Some(preset) = self.bank(*mode).get(*index).cloned() {
+                    self.insert(*mode, index + 1, preset);
+                }
+            }
+
+            PresetEvent::Move { mode, from, to } => self.move_preset(*mode, *from, *to),
+
+            PresetEvent::Delete { mode, index } => self.delete(*mode, *index),
+
+            PresetEvent::Insert {
+                mode,
+                index,
+                preset,
+            } => self.insert(*mode, *index, preset.clone()),
         });
 
         // App event
-        /* event.map(|app_event, _| match app_event {
-            AppEvent::DcToggle => {
+        event.map(|app_event, _| match app_event {
+            /* AppEvent::DcToggle => {
                 self.dc = !self.dc;
+            } */
+            AppEvent::Interpolation(interpolation) => {
+                self.record(None);
+
+                if let Some(preset) = self.selected_preset() {
+                    preset.curve.interpolation = *interpolation;
+                }
+
+                self.store(self.mode);
+                self.sync_selected();
             }
 
-            AppEvent::Interpolation(interpolation) => {
-                self.interpolation = *interpolation;
+            AppEvent::LengthCount(step) => {
+                let count = self.length.count as i32 + step;
+                self.set_length(Length {
+                    count: count.clamp(1, Length::MAX_COUNT as i32) as u32,
+                    ..self.length
+                });
+            }
+
+            AppEvent::LengthUnit(unit) => self.set_length(Length {
+                unit: *unit,
+                ..self.length
+            }),
+
+            AppEvent::Snap(snap) => {
+                self.snap = *snap;
+            }
+
+            AppEvent::ContextMenu(menu) => {
+                self.context_menu = *menu;
+            }
+
+            AppEvent::LearnScratch => {
+                self.params.scratch_learn.fetch_xor(true, Ordering::Relaxed);
+                self.refresh();
+            }
+
+            AppEvent::ClearScratch => {
+                self.params.scratch_learn.store(false, Ordering::Relaxed);
+                self.params
+                    .scratch_control
+                    .store(ScratchControl::Off.to_raw(), Ordering::Relaxed);
+                self.refresh();
+            }
+
+            AppEvent::Refresh => self.refresh(),
+
+            AppEvent::Transform(transform) => {
+                if let Err(err) = self.transform(*transform) {
+                    nih_error!("Could not transform the curve: {err}");
+                }
+            }
+
+            AppEvent::Generate(generator) => {
+                if let Err(err) = self.generate(*generator) {
+                    nih_error!("Could not generate the curve: {err}");
+                }
+            }
+
+            AppEvent::ImportPreset => {
+                if let Err(err) = self.import_preset() {
+                    nih_error!("Could not load preset: {err}");
+                }
             }
-        }) */
+
+            AppEvent::ExportPreset => {
+                if let Err(err) = self.export_preset() {
+                    nih_error!("Could not save preset: {err}");
+                }
+            }
+
+            AppEvent::ImportBank => {
+                if let Err(err) = self.import_bank() {
+                    nih_error!("Could not load bank: {err}");
+                }
+            }
+
+            AppEvent::ExportBank => {
+                if let Err(err) = self.export_bank() {
+                    nih_error!("Could not save bank: {err}");
+                }
+            }
+
+            AppEvent::Browse { mode, step } => {
+                // The bank comes before all categories.
+                let count = library::categories(&self.library, *mode).len() as isize + 1;
+                let browse = self.browse(*mode);
+                browse.category = (browse.category as isize + step).rem_euclid(count) as usize;
+            }
+
+            AppEvent::Search { mode, text } => {
+                self.browse(*mode).search = text.clone();
+            }
+        });
     }
 }
 
@@ -115,7 +907,6 @@ pub(crate) fn default_state() -> Arc<ViziaState> {
     ViziaState::new(|| SIZE)
 }
 
-/*
 fn interpolation_checkbox(cx: &mut Context, text: &str, interpolation: Interpolation) {
     Label::new(cx, text)
         .font_size(12.0)
@@ -129,27 +920,209 @@ fn interpolation_checkbox(cx: &mut Context, text: &str, interpolation: Interpola
         .right(Pixels(7.0))
         .size(Pixels(18.0));
 }
-*/
+
+fn snap_checkbox(cx: &mut Context, text: &str, snap: Snap) {
+    Label::new(cx, text)
+        .font_size(12.0)
+        .top(Pixels(1.0))
+        .right(Pixels(2.0));
+
+    Checkbox::new(cx, AppData::snap.map(move |x| *x == snap))
+        .on_toggle(move |cx| cx.emit(AppEvent::Snap(snap)))
+        .background_color(Color::rgb(127, 127, 127))
+        .border_radius(Pixels(9.0))
+        .right(Pixels(7.0))
+        .size(Pixels(18.0));
+}
+
+/// A button that changes the number of units in the length of the selected
+/// preset by `step`.
+fn length_button(cx: &mut Context, text: &'static str, step: i32) {
+    Button::new(
+        cx,
+        move |cx| cx.emit(AppEvent::LengthCount(step)),
+        move |cx| Label::new(cx, text).font_size(12.0),
+    )
+    .right(Pixels(4.0));
+}
+
+/// The menu of units the length of the selected preset is counted in.
+fn length_unit_menu(cx: &mut Context) {
+    Dropdown::new(
+        cx,
+        |cx| Label::new(cx, AppData::length.map(|length| length.unit.name())).font_size(12.0),
+        |cx| {
+            for unit in LengthUnit::ALL {
+                menu_item(cx, unit.name(), move || AppEvent::LengthUnit(unit));
+            }
+        },
+    )
+    .width(Pixels(70.0))
+    .right(Pixels(4.0));
+}
+
+/// A button that sends an app event.
+fn app_button(cx: &mut Context, text: &'static str, event: fn() -> AppEvent) {
+    Button::new(
+        cx,
+        move |cx| cx.emit(event()),
+        move |cx| Label::new(cx, text).font_size(12.0),
+    )
+    .right(Pixels(4.0));
+}
+
+/// An entry in a dropdown menu.
+fn menu_item(cx: &mut Context, text: &'static str, event: impl 'static + Fn() -> AppEvent) {
+    Button::new(
+        cx,
+        move |cx| {
+            cx.emit(event());
+            cx.emit(PopupEvent::Close);
+        },
+        move |cx| Label::new(cx, text).font_size(12.0),
+    )
+    .width(Stretch(1.0));
+}
+
+/// The menu of operations on the curve of the selected preset.
+fn transform_menu(cx: &mut Context) {
+    Dropdown::new(
+        cx,
+        |cx| Label::new(cx, "Transform").font_size(12.0),
+        |cx| {
+            let transforms = [
+                ("Flip", Transform::Flip),
+                ("Invert", Transform::Invert),
+                ("Shift left", Transform::ShiftLeft),
+                ("Shift right", Transform::ShiftRight),
+                ("Halve", Transform::Halve),
+                ("Double", Transform::Double),
+                ("Repeat 2x", Transform::Repeat(2)),
+                ("Repeat 3x", Transform::Repeat(3)),
+                ("Repeat 4x", Transform::Repeat(4)),
+                ("Quantize", Transform::Quantize),
+                ("Smooth", Transform::Smooth),
+                ("Simplify", Transform::Simplify),
+            ];
+
+            for (text, transform) in transforms {
+                menu_item(cx, text, move || AppEvent::Transform(transform));
+            }
+        },
+    )
+    .width(Pixels(100.0))
+    .right(Pixels(4.0));
+}
+
+/// The menu of curves to generate for the selected preset, leaving out the ones
+/// that don't fit its mode.
+fn generate_menu(cx: &mut Context) {
+    Dropdown::new(
+        cx,
+        |cx| Label::new(cx, "Generate").font_size(12.0),
+        |cx| {
+            let generators = [
+                ("Sine", Generator::Lfo(LfoShape::Sine)),
+                ("Triangle", Generator::Lfo(LfoShape::Triangle)),
+                ("Saw", Generator::Lfo(LfoShape::Saw)),
+                ("Square", Generator::Lfo(LfoShape::Square)),
+                (
+                    "Euclid 3/8",
+                    Generator::Euclidean {
+                        pulses: 3,
+                        steps: 8,
+                    },
+                ),
+                (
+                    "Euclid 5/8",
+                    Generator::Euclidean {
+                        pulses: 5,
+                        steps: 8,
+                    },
+                ),
+                (
+                    "Euclid 5/16",
+                    Generator::Euclidean {
+                        pulses: 5,
+                        steps: 16,
+                    },
+                ),
+                (
+                    "Euclid 7/16",
+                    Generator::Euclidean {
+                        pulses: 7,
+                        steps: 16,
+                    },
+                ),
+                ("Random", Generator::Random),
+                ("Envelope", Generator::Envelope),
+            ];
+
+            Binding::new(cx, AppData::mode, move |cx, mode| {
+                let mode = mode.get(cx);
+                for (text, generator) in generators {
+                    if generator.fits(mode) {
+                        menu_item(cx, text, move || AppEvent::Generate(generator));
+                    }
+                }
+            });
+        },
+    )
+    .width(Pixels(100.0))
+    .right(Pixels(4.0));
+}
 
 pub(crate) fn create(
     params: Arc<FunctorParams>,
+    async_executor: AsyncExecutor<Functor>,
+    playheads: Arc<Playheads>,
+    shared_library: Arc<Library>,
     editor_state: Arc<ViziaState>,
 ) -> Option<Box<dyn nih_plug::editor::Editor>> {
-    create_vizia_editor(editor_state, ViziaTheming::Custom, move |cx, _| {
+    create_vizia_editor(editor_state, ViziaTheming::Custom, move |cx, context| {
         assets::register_noto_sans_light(cx);
         assets::register_noto_sans_thin(cx);
 
-          AppData {
-                params: params.clone(),
-                mode: Mode::Beat,
-                index: 0,
-                beat_presets: test_presets(12),
-                vol_presets: test_presets(12),
-                dc: true,
-                //interpolation: Interpolation::Hermite,
+        let mut app_data = AppData {
+            params: params.clone(),
+            gui_context: context.clone(),
+            playheads: playheads.clone(),
+            playhead: Playhead {
+                beat: -1.0,
+                vol: -1.0,
+            },
+            scratch: String::new(),
+            async_executor: async_executor.clone(),
+            mode: Mode::Beat,
+            index: 0,
+            context_menu: None,
+            beat_presets: params.beat_presets.read().unwrap().clone(),
+            vol_presets: params.vol_presets.read().unwrap().clone(),
+            shared_library: shared_library.clone(),
+            library_generation: shared_library.generation(),
+            library: shared_library.presets(),
+            beat_browse: Browse::default(),
+            vol_browse: Browse::default(),
+            dc: true,
+            name: String::new(),
+            history: History::default(),
+            interpolation: Interpolation::default(),
+            length: Length::default(),
+            snap: Snap::default(),
+        };
+        app_data.sync_selected();
+        app_data.refresh();
+        app_data.build(cx);
+
+        // Pick up any presets added to the library since the last scan.
+        async_executor.execute_background(Task::ScanLibrary);
+
+        let timer = cx.add_timer(REFRESH_INTERVAL, None, |cx, action| {
+            if let TimerAction::Tick(_) = action {
+                cx.emit(AppEvent::Refresh);
             }
-            .build(cx);
-        
+        });
+        cx.start_timer(timer);
 
         /*
         HStack::new(cx, |cx| {
@@ -192,11 +1165,15 @@ pub(crate) fn create(
                 .bottom(Pixels(5.0)); */
 
                 // Beat list
-                // ScrollView::new(cx, 0.0, 0.0, false, true, |cx| {
-                FunctorList::new(cx, Mode::Beat, AppData::beat_presets) //;
-                    // })
-                    .height(Pixels(140.0))
-                    .bottom(Pixels(10.0));
+                FunctorList::new(
+                    cx,
+                    Mode::Beat,
+                    AppData::beat_presets,
+                    AppData::library,
+                    AppData::beat_browse,
+                )
+                .height(Pixels(140.0))
+                .bottom(Pixels(10.0));
 
                 // Gate label
                 /* Label::new(cx, "Gate")
@@ -205,65 +1182,229 @@ pub(crate) fn create(
                 .bottom(Pixels(5.0)); */
 
                 // Gate list
-                //ScrollView::new(cx, 0.0, 0.0, false, true, |cx| {
-                FunctorList::new(cx, Mode::Vol, AppData::vol_presets) //;
-                    //   })
-                    .height(Pixels(140.0))
-                    .bottom(Pixels(20.0));
+                FunctorList::new(
+                    cx,
+                    Mode::Vol,
+                    AppData::vol_presets,
+                    AppData::library,
+                    AppData::vol_browse,
+                )
+                .height(Pixels(140.0))
+                .bottom(Pixels(20.0));
 
                 // Advanced label
-                /*  VStack::new(cx, |cx| {
-                            Label::new(cx, "Advanced")
-                                .left(Pixels(3.0))
-                                .bottom(Pixels(10.0))
-                                .font_size(20.0);
-
-                            Label::new(cx, "Interpolation")
-                                .font_size(14.0)
-                                .left(Pixels(12.0))
-                                .bottom(Pixels(2.0));
-
-                            HStack::new(cx, |cx| {
-                                interpolation_checkbox(cx, "D", Interpolation::Drop);
-                                interpolation_checkbox(cx, "L", Interpolation::Linear);
-                                interpolation_checkbox(cx, "C", Interpolation::Cubic);
-                                interpolation_checkbox(cx, "H", Interpolation::Hermite);
-                            })
+                VStack::new(cx, |cx| {
+                    Label::new(cx, "Advanced")
+                        .left(Pixels(3.0))
+                        .bottom(Pixels(10.0))
+                        .font_size(20.0);
+
+                    HStack::new(cx, |cx| {
+                        Label::new(cx, "Sync")
+                            .font_size(14.0)
+                            .top(Pixels(3.0))
+                            .right(Pixels(6.0));
+
+                        ParamSlider::new(cx, AppData::params, |params| &params.sync)
+                            .font_size(12.0)
+                            .height(Pixels(22.0))
+                            .width(Pixels(70.0))
+                            .right(Pixels(4.0));
+                        ParamSlider::new(cx, AppData::params, |params| &params.rate)
+                            .font_size(12.0)
+                            .height(Pixels(22.0))
+                            .width(Pixels(100.0));
+                    })
+                    .left(Pixels(12.0))
+                    .height(Pixels(30.0));
+
+                    HStack::new(cx, |cx| {
+                        Label::new(cx, "Mix")
+                            .font_size(14.0)
+                            .top(Pixels(3.0))
+                            .right(Pixels(6.0));
+
+                        ParamSlider::new(cx, AppData::params, |params| &params.beat_mix)
+                            .font_size(12.0)
+                            .height(Pixels(22.0))
+                            .width(Pixels(56.0))
+                            .right(Pixels(4.0));
+                        ParamSlider::new(cx, AppData::params, |params| &params.vol_mix)
+                            .font_size(12.0)
+                            .height(Pixels(22.0))
+                            .width(Pixels(56.0))
+                            .right(Pixels(4.0));
+                        ParamSlider::new(cx, AppData::params, |params| &params.output_gain)
+                            .font_size(12.0)
+                            .height(Pixels(22.0))
+                            .width(Pixels(80.0));
+                    })
+                    .left(Pixels(12.0))
+                    .height(Pixels(30.0));
+
+                    HStack::new(cx, |cx| {
+                        Label::new(cx, "Env")
+                            .font_size(14.0)
+                            .top(Pixels(3.0))
+                            .right(Pixels(6.0));
+
+                        ParamSlider::new(cx, AppData::params, |params| &params.vol_attack)
+                            .font_size(12.0)
+                            .height(Pixels(22.0))
+                            .width(Pixels(80.0))
+                            .right(Pixels(4.0));
+                        ParamSlider::new(cx, AppData::params, |params| &params.vol_release)
+                            .font_size(12.0)
+                            .height(Pixels(22.0))
+                            .width(Pixels(80.0));
+                    })
+                    .left(Pixels(12.0))
+                    .height(Pixels(30.0));
+
+                    HStack::new(cx, |cx| {
+                        Label::new(cx, "Beat")
+                            .font_size(14.0)
+                            .top(Pixels(3.0))
+                            .right(Pixels(6.0));
+
+                        ParamSlider::new(cx, AppData::params, |params| &params.beat_crossfade)
+                            .font_size(12.0)
+                            .height(Pixels(22.0))
+                            .width(Pixels(60.0))
+                            .right(Pixels(4.0));
+                        ParamSlider::new(cx, AppData::params, |params| &params.resampling)
+                            .font_size(12.0)
+                            .height(Pixels(22.0))
+                            .width(Pixels(64.0))
+                            .right(Pixels(4.0));
+                        ParamSlider::new(cx, AppData::params, |params| &params.bypass)
+                            .font_size(12.0)
+                            .height(Pixels(22.0))
+                            .width(Pixels(56.0));
+                    })
+                    .left(Pixels(12.0))
+                    .height(Pixels(30.0));
+
+                    HStack::new(cx, |cx| {
+                        Label::new(cx, "Scratch")
+                            .font_size(14.0)
+                            .top(Pixels(3.0))
+                            .right(Pixels(6.0));
+
+                        Label::new(cx, AppData::scratch)
+                            .font_size(12.0)
+                            .top(Pixels(4.0))
+                            .width(Pixels(50.0));
+
+                        app_button(cx, "Learn", || AppEvent::LearnScratch);
+                        app_button(cx, "Clear", || AppEvent::ClearScratch);
+                    })
+                    .left(Pixels(12.0))
+                    .height(Pixels(30.0));
+
+                    Label::new(cx, "Interpolation")
+                        .font_size(14.0)
+                        .left(Pixels(12.0))
+                        .bottom(Pixels(2.0));
+
+                    HStack::new(cx, |cx| {
+                        interpolation_checkbox(cx, "D", Interpolation::Drop);
+                        interpolation_checkbox(cx, "L", Interpolation::Linear);
+                        interpolation_checkbox(cx, "C", Interpolation::Cubic);
+                        interpolation_checkbox(cx, "H", Interpolation::Hermite);
+                    })
+                    .left(Pixels(12.0))
+                    .height(Pixels(30.0));
+
+                    Label::new(cx, "Snap")
+                        .font_size(14.0)
+                        .left(Pixels(12.0))
+                        .bottom(Pixels(2.0));
+
+                    HStack::new(cx, |cx| {
+                        snap_checkbox(cx, "-", Snap::Off);
+                        snap_checkbox(cx, "4", Snap::Quarter);
+                        snap_checkbox(cx, "8", Snap::Eighth);
+                        snap_checkbox(cx, "16", Snap::Sixteenth);
+                    })
+                    .left(Pixels(12.0))
+                    .height(Pixels(24.0));
+
+                    HStack::new(cx, |cx| {
+                        snap_checkbox(cx, "4T", Snap::QuarterTriplet);
+                        snap_checkbox(cx, "8T", Snap::EighthTriplet);
+                        snap_checkbox(cx, "16T", Snap::SixteenthTriplet);
+                    })
+                    .left(Pixels(12.0))
+                    .height(Pixels(30.0));
+
+                    HStack::new(cx, |cx| {
+                        Label::new(cx, "Length")
+                            .font_size(14.0)
+                            .top(Pixels(3.0))
+                            .right(Pixels(6.0));
+
+                        length_button(cx, "-", -1);
+                        Label::new(cx, AppData::length.map(|length| length.count.to_string()))
+                            .font_size(12.0)
+                            .top(Pixels(4.0))
+                            .width(Pixels(24.0));
+                        length_button(cx, "+", 1);
+                        length_unit_menu(cx);
+                    })
+                    .left(Pixels(12.0))
+                    .height(Pixels(30.0));
+
+                    HStack::new(cx, |cx| {
+                        transform_menu(cx);
+                        generate_menu(cx);
+                    })
+                    .left(Pixels(12.0))
+                    .height(Pixels(30.0));
+
+                    HStack::new(cx, |cx| {
+                        app_button(cx, "Load", || AppEvent::ImportPreset);
+                        app_button(cx, "Save", || AppEvent::ExportPreset);
+                        app_button(cx, "Load bank", || AppEvent::ImportBank);
+                        app_button(cx, "Save bank", || AppEvent::ExportBank);
+                    })
+                    .left(Pixels(12.0))
+                    .top(Pixels(6.0))
+                    .height(Pixels(30.0));
+
+                    /* HStack::new(cx, |cx| {
+                        Label::new(cx, "DC offset")
+                            .font_size(14.0)
                             .left(Pixels(12.0))
-                            .height(Pixels(30.0));
-
-                            HStack::new(cx, |cx| {
-                                Label::new(cx, "DC offset")
-                                    .font_size(14.0)
-                                    .left(Pixels(12.0))
-                                    .right(Pixels(2.0));
-
-                                Checkbox::new(cx, AppData::dc)
-                                    .on_toggle(|cx| cx.emit(AppEvent::DcToggle))
-                                    .background_color(Color::rgb(127, 127, 127))
-                                    .top(Pixels(1.0))
-                                    .size(Pixels(16.0));
-                            });
-                        })
-                        .background_color(Color::rgb(220, 220, 220))
-                        .border_radius(Pixels(10.0));
-                */
+                            .right(Pixels(2.0));
+
+                        Checkbox::new(cx, AppData::dc)
+                            .on_toggle(|cx| cx.emit(AppEvent::DcToggle))
+                            .background_color(Color::rgb(127, 127, 127))
+                            .top(Pixels(1.0))
+                            .size(Pixels(16.0));
+                    }); */
+                })
+                .background_color(Color::rgb(220, 220, 220))
+                .border_radius(Pixels(10.0));
             })
             .left(Pixels(10.0))
             .bottom(Pixels(10.0))
             .width(Pixels(262.0));
 
             // Curve view
-            /* curve_view::CurveView::new(
+            CurveView::new(
                 cx,
                 AppData::beat_presets,
                 AppData::vol_presets,
                 AppData::mode,
                 AppData::index,
+                AppData::snap,
+                AppData::playhead,
             )
             .left(Pixels(15.0))
             .right(Pixels(20.0))
-            .size(Pixels(500.0)); */
+            .size(Pixels(500.0));
         });
     })
 }
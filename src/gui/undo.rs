@@ -0,0 +1,126 @@
+use std::collections::VecDeque;
+use std::mem;
+
+use crate::curve::{Mode, Node, Preset};
+
+/// Roughly how much memory the undo history may take up, in bytes. The oldest
+/// steps are forgotten once it grows past this.
+const MEMORY_BUDGET: usize = 16 * 1024 * 1024;
+
+/// An event relating to the undo history.
+pub enum UndoEvent {
+    /// Go back to before the last change.
+    Undo,
+
+    /// Redo the last change that was undone.
+    Redo,
+
+    /// End the current edit, so that the next change gets its own step even if
+    /// it's to the same preset. This is sent when a drag ends.
+    Checkpoint,
+}
+
+/// A kind of change that can go on for many events, like dragging a node,
+/// which only gets a single step in the history.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Edit {
+    /// Editing the curve of a preset.
+    Curve {
+        /// Whether the preset is a beat or a volume.
+        mode: Mode,
+        /// The index of the preset.
+        index: usize,
+    },
+}
+
+/// Both banks, the selection and the slots the preset parameters point at, at
+/// some point in time.
+#[derive(Clone)]
+pub struct Snapshot {
+    pub beat_presets: Vec<Preset>,
+    pub vol_presets: Vec<Preset>,
+    pub mode: Mode,
+    pub index: usize,
+    pub beat_slot: usize,
+    pub vol_slot: usize,
+}
+
+impl Snapshot {
+    /// Roughly how much memory the snapshot takes up, in bytes.
+    fn size(&self) -> usize {
+        let bank_size = |bank: &[Preset]| -> usize {
+            bank.iter()
+                .map(|preset| {
+                    mem::size_of::<Preset>()
+                        + preset.name.capacity()
+                        + preset.curve.nodes().len() * mem::size_of::<Node>()
+                })
+                .sum()
+        };
+
+        mem::size_of::<Self>() + bank_size(&self.beat_presets) + bank_size(&self.vol_presets)
+    }
+}
+
+/// The states to go back or forwards to.
+#[derive(Default)]
+pub struct History {
+    /// The states before every change, oldest first.
+    undo: VecDeque<Snapshot>,
+    /// The states that were undone, most recently undone last.
+    redo: Vec<Snapshot>,
+    /// The total size of all snapshots, in bytes.
+    size: usize,
+    /// The edit the last step was recorded for, while it's still going on.
+    edit: Option<Edit>,
+}
+
+impl History {
+    /// Records the state right before a change. If the change continues an edit
+    /// that was already recorded, the state from before that edit is kept
+    /// instead.
+    pub fn record(&mut self, before: Snapshot, edit: Option<Edit>) {
+        if edit.is_some() && edit == self.edit {
+            return;
+        }
+
+        self.edit = edit;
+        self.size -= self.redo.iter().map(Snapshot::size).sum::<usize>();
+        self.redo.clear();
+        self.size += before.size();
+        self.undo.push_back(before);
+
+        while self.size > MEMORY_BUDGET && self.undo.len() > 1 {
+            if let Some(oldest) = self.undo.pop_front() {
+                self.size -= oldest.size();
+            }
+        }
+    }
+
+    /// Ends the current edit.
+    pub fn checkpoint(&mut self) {
+        self.edit = None;
+    }
+
+    /// Goes back one step from the current state, if possible, returning the
+    /// state to restore.
+    pub fn undo(&mut self, current: Snapshot) -> Option<Snapshot> {
+        let previous = self.undo.pop_back()?;
+        self.edit = None;
+        self.size += current.size();
+        self.size -= previous.size();
+        self.redo.push(current);
+        Some(previous)
+    }
+
+    /// Goes forwards one step from the current state, if possible, returning
+    /// the state to restore.
+    pub fn redo(&mut self, current: Snapshot) -> Option<Snapshot> {
+        let next = self.redo.pop()?;
+        self.edit = None;
+        self.size += current.size();
+        self.size -= next.size();
+        self.undo.push_back(current);
+        Some(next)
+    }
+}
@@ -1,10 +1,44 @@
 mod gui;
 mod curve;
+mod dsp;
+mod factory;
+mod file;
+mod generate;
+mod library;
+mod migrate;
 
-use std::sync::Arc;
+use atomic_float::AtomicF32;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 
 use nih_plug::prelude::*;
 use nih_plug_vizia::ViziaState;
+use triple_buffer::TripleBuffer;
+
+use crate::curve::{Length, Mode, Preset};
+use crate::dsp::beat::BeatEngine;
+use crate::dsp::clock::{self, Clock, SyncMode};
+use crate::dsp::resample::Resampling;
+use crate::dsp::scratch::ScratchControl;
+use crate::dsp::table::{CurveTable, Tables};
+use crate::dsp::trigger::{self, Trigger};
+use crate::dsp::vol::VolEngine;
+use crate::dsp::Timing;
+use crate::library::Library;
+
+/// How long bypassing fades between the processed and the untouched audio, in
+/// milliseconds.
+const BYPASS_FADE_MS: f32 = 10.0;
+
+/// How long the mixes take to follow their parameters, in milliseconds.
+const MIX_SMOOTHING_MS: f32 = 20.0;
+
+/// How long the output gain takes to follow its parameter, in milliseconds.
+const GAIN_SMOOTHING_MS: f32 = 50.0;
+
+/// How many presets fit in a bank, which is as many as the parameters picking
+/// them and the trigger notes can reach.
+pub const MAX_PRESETS: usize = 36;
 
 /// The parameters for the Functor plugin.
 #[derive(Params)]
@@ -20,23 +54,278 @@ pub struct FunctorParams {
     /// The selected volume or gating pattern.
     #[id = "vol"]
     pub vol: IntParam,
+
+    /// What moves the curves forwards.
+    #[id = "sync"]
+    pub sync: EnumParam<SyncMode>,
+
+    /// How many bars pass per second when not following the host.
+    #[id = "rate"]
+    pub rate: FloatParam,
+
+    /// How much of the beat engine is heard over the input.
+    #[id = "beat-mix"]
+    pub beat_mix: FloatParam,
+
+    /// How much of the volume engine is heard over its input.
+    #[id = "vol-mix"]
+    pub vol_mix: FloatParam,
+
+    /// The gain applied to the processed audio.
+    #[id = "output-gain"]
+    pub output_gain: FloatParam,
+
+    /// How long the volume takes to rise towards its curve. Like the other
+    /// times, this is only read once per block.
+    #[id = "vol-attack"]
+    pub vol_attack: FloatParam,
+
+    /// How long the volume takes to fall towards its curve, read once per
+    /// block.
+    #[id = "vol-release"]
+    pub vol_release: FloatParam,
+
+    /// How long the beat engine crossfades over when it jumps, read once per
+    /// block. A crossfade that's already running keeps its speed until then.
+    #[id = "beat-crossfade"]
+    pub beat_crossfade: FloatParam,
+
+    /// How the beat engine reads audio in between samples.
+    #[id = "resampling"]
+    pub resampling: EnumParam<Resampling>,
+
+    /// Passes the input through untouched.
+    #[id = "bypass"]
+    pub bypass: BoolParam,
+
+    /// The MIDI controller that scratches the beat engine, as stored by
+    /// [`ScratchControl::to_raw`].
+    #[persist = "scratch-control"]
+    pub scratch_control: AtomicI32,
+
+    /// Whether the next controller to move should become the scratch control.
+    pub scratch_learn: AtomicBool,
+
+    /// The presets for the beats. These are saved along with the project.
+    #[persist = "beat-presets"]
+    pub beat_presets: RwLock<Vec<Preset>>,
+
+    /// The presets for the volumes. These are saved along with the project.
+    #[persist = "vol-presets"]
+    pub vol_presets: RwLock<Vec<Preset>>,
 }
 
 impl Default for FunctorParams {
     fn default() -> Self {
         Self {
             editor_state: gui::default_state(),
-            beat: IntParam::new("beat", 0, IntRange::Linear { min: 0, max: 35 }),
-            vol: IntParam::new("vol", 0, IntRange::Linear { min: 0, max: 35 }),
+            beat: preset_param("beat"),
+            vol: preset_param("vol"),
+            sync: EnumParam::new("sync", SyncMode::Host),
+            rate: FloatParam::new(
+                "rate",
+                0.5,
+                FloatRange::Skewed {
+                    min: 0.05,
+                    max: 20.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(2))
+            .with_string_to_value(clock::s2v_rate()),
+            beat_mix: mix_param("beat mix"),
+            vol_mix: mix_param("vol mix"),
+            output_gain: FloatParam::new(
+                "output gain",
+                util::db_to_gain(0.0),
+                FloatRange::Skewed {
+                    min: util::db_to_gain(-30.0),
+                    max: util::db_to_gain(12.0),
+                    factor: FloatRange::gain_skew_factor(-30.0, 12.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(GAIN_SMOOTHING_MS))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+            vol_attack: time_param("vol attack", 1.0, 200.0),
+            vol_release: time_param("vol release", 1.0, 500.0),
+            beat_crossfade: time_param("beat crossfade", 5.0, 50.0),
+            resampling: EnumParam::new("resampling", Resampling::Cubic),
+            bypass: BoolParam::new("bypass", false).make_bypass(),
+            scratch_control: AtomicI32::new(ScratchControl::Off.to_raw()),
+            scratch_learn: AtomicBool::new(false),
+            beat_presets: RwLock::new(factory::beat_presets()),
+            vol_presets: RwLock::new(factory::vol_presets()),
+        }
+    }
+}
+
+/// A parameter picking a slot in one of the banks.
+fn preset_param(name: &str) -> IntParam {
+    IntParam::new(
+        name,
+        0,
+        IntRange::Linear {
+            min: 0,
+            max: MAX_PRESETS as i32 - 1,
+        },
+    )
+}
+
+/// A dry/wet mix from 0 to 100%.
+fn mix_param(name: &str) -> FloatParam {
+    FloatParam::new(name, 1.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+        .with_smoother(SmoothingStyle::Linear(MIX_SMOOTHING_MS))
+        .with_unit("%")
+        .with_value_to_string(formatters::v2s_f32_percentage(0))
+        .with_string_to_value(formatters::s2v_f32_percentage())
+}
+
+/// A time in milliseconds, from zero up to `max`. These aren't smoothed, and
+/// the engines only pick up new values at the start of every block, so
+/// automation on them isn't sample accurate.
+fn time_param(name: &str, default: f32, max: f32) -> FloatParam {
+    FloatParam::new(
+        name,
+        default,
+        FloatRange::Skewed {
+            min: 0.0,
+            max,
+            factor: FloatRange::skew_factor(-2.0),
+        },
+    )
+    .with_unit(" ms")
+    .with_value_to_string(formatters::v2s_f32_rounded(1))
+    .with_string_to_value(s2v_ms())
+}
+
+/// Parses a time in milliseconds, or in seconds if it ends in `s` but not in
+/// `ms`.
+fn s2v_ms() -> Arc<dyn Fn(&str) -> Option<f32> + Send + Sync> {
+    Arc::new(|string| {
+        let string = string.trim().to_lowercase();
+        if let Some(ms) = string.strip_suffix("ms") {
+            ms.trim().parse().ok()
+        } else if let Some(seconds) = string.strip_suffix('s') {
+            let seconds: f32 = seconds.trim().parse().ok()?;
+            Some(seconds * 1000.0)
+        } else {
+            string.parse().ok()
         }
+    })
+}
+
+/// A task that's run off of the audio thread.
+pub enum Task {
+    /// Rebuild the lookup tables from the preset banks and hand them over to
+    /// the audio thread.
+    RebuildTables,
+
+    /// Look for presets in the library folder.
+    ScanLibrary,
+}
+
+/// Where the audio thread is within the active beat and volume curves as of
+/// the last block, from 0 to 1, or negative numbers if the curves aren't
+/// running. These are shown in the editor.
+pub struct Playheads {
+    /// The position within the beat curve.
+    pub beat: AtomicF32,
+
+    /// The position within the volume curve.
+    pub vol: AtomicF32,
+}
+
+impl Default for Playheads {
+    fn default() -> Self {
+        Self {
+            beat: AtomicF32::new(-1.0),
+            vol: AtomicF32::new(-1.0),
+        }
+    }
+}
+
+impl Playheads {
+    /// Stores the positions within the beat and volume curves, each played with
+    /// its own timing and looped over its own length, at a sample in a block.
+    fn update(&self, sample: usize, beat: (Option<Timing>, Length), vol: (Option<Timing>, Length)) {
+        let phase = |(timing, length): (Option<Timing>, Length)| {
+            timing.map_or(-1.0, |timing| timing.phase(sample, length))
+        };
+
+        self.beat.store(phase(beat), Ordering::Relaxed);
+        self.vol.store(phase(vol), Ordering::Relaxed);
     }
 }
 
 /// The functor plugin and all of the memory it needs to allocate.
-#[derive(Default)]
 pub struct Functor {
     /// The parameters for the plugin.
     params: Arc<FunctorParams>,
+
+    /// The lookup tables for the preset banks. These are swapped in without
+    /// locking whenever the banks change.
+    tables: triple_buffer::Output<Tables>,
+
+    /// The other end of `tables`, where new lookup tables are written.
+    tables_input: Arc<Mutex<triple_buffer::Input<Tables>>>,
+
+    /// Whether the banks may have changed since `tables` were built, without
+    /// a rebuild having been asked for yet.
+    tables_stale: bool,
+
+    /// The beat or timestretch engine.
+    beat_engine: BeatEngine,
+
+    /// The volume or gating engine.
+    vol_engine: VolEngine,
+
+    /// The clock the curves follow when not synced to the host.
+    clock: Clock,
+
+    /// The input of every channel for the current block, to fall back to
+    /// while bypassed.
+    dry: Vec<Vec<f32>>,
+
+    /// How much of the processed audio is heard, fading in and out as the
+    /// plugin is bypassed.
+    active: Smoother<f32>,
+
+    /// The beat preset picked by a held note, if any.
+    beat_trigger: Trigger,
+
+    /// The volume preset picked by a held note, if any.
+    vol_trigger: Trigger,
+
+    /// Where the audio thread is within the active curves, for the editor.
+    playheads: Arc<Playheads>,
+
+    /// The presets in the user's library, for the editor.
+    library: Arc<Library>,
+}
+
+impl Default for Functor {
+    fn default() -> Self {
+        let (tables_input, tables) = TripleBuffer::new(&Tables::default()).split();
+
+        Self {
+            params: Arc::default(),
+            tables,
+            tables_input: Arc::new(Mutex::new(tables_input)),
+            tables_stale: true,
+            beat_engine: BeatEngine::default(),
+            vol_engine: VolEngine::default(),
+            clock: Clock::default(),
+            dry: Vec::new(),
+            active: Smoother::new(SmoothingStyle::Linear(BYPASS_FADE_MS)),
+            beat_trigger: Trigger::default(),
+            vol_trigger: Trigger::default(),
+            playheads: Arc::default(),
+            library: Arc::default(),
+        }
+    }
 }
 
 impl Plugin for Functor {
@@ -59,30 +348,219 @@ impl Plugin for Functor {
         },
     ];
 
+    const MIDI_INPUT: MidiConfig = MidiConfig::MidiCCs;
     const SAMPLE_ACCURATE_AUTOMATION: bool = true;
 
     type SysExMessage = ();
-    type BackgroundTask = ();
+    type BackgroundTask = Task;
 
     fn params(&self) -> Arc<dyn Params> {
         self.params.clone()
     }
 
-    fn editor(&mut self, _: AsyncExecutor<Self>) -> Option<Box<dyn Editor>> {
-        gui::create(self.params.clone(), self.params.editor_state.clone())
+    fn task_executor(&mut self) -> TaskExecutor<Self> {
+        let params = self.params.clone();
+        let tables_input = self.tables_input.clone();
+        let library = self.library.clone();
+
+        Box::new(move |task| match task {
+            Task::RebuildTables => rebuild_tables(&params, &tables_input),
+            Task::ScanLibrary => library.rescan(),
+        })
+    }
+
+    fn editor(&mut self, async_executor: AsyncExecutor<Self>) -> Option<Box<dyn Editor>> {
+        gui::create(
+            self.params.clone(),
+            async_executor,
+            self.playheads.clone(),
+            self.library.clone(),
+            self.params.editor_state.clone(),
+        )
+    }
+
+    fn initialize(
+        &mut self,
+        audio_io_layout: &AudioIOLayout,
+        buffer_config: &BufferConfig,
+        _context: &mut impl InitContext<Self>,
+    ) -> bool {
+        let num_channels = audio_io_layout
+            .main_output_channels
+            .map_or(0, NonZeroU32::get) as usize;
+
+        let max_block = buffer_config.max_buffer_size as usize;
+        self.beat_engine
+            .initialize(num_channels, buffer_config.sample_rate, max_block);
+        self.vol_engine
+            .initialize(buffer_config.sample_rate, max_block);
+        self.dry = vec![vec![0.0; max_block]; num_channels];
+        self.active
+            .reset(if self.params.bypass.value() { 0.0 } else { 1.0 });
+
+        // The banks may have just been restored from the project. The tables
+        // are rebuilt in the background once processing starts, and the old
+        // ones are used until then.
+        self.tables_stale = true;
+        true
+    }
+
+    fn reset(&mut self) {
+        self.beat_engine.reset();
+        self.vol_engine.reset();
+        self.clock.reset();
+        self.beat_trigger.reset();
+        self.vol_trigger.reset();
     }
 
-    // Do nothing for now
     fn process(
         &mut self,
-        _buffer: &mut Buffer,
+        buffer: &mut Buffer,
         _aux: &mut AuxiliaryBuffers,
-        _context: &mut impl ProcessContext<Self>,
+        context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
+        if std::mem::take(&mut self.tables_stale) {
+            context.execute_background(Task::RebuildTables);
+        }
+
+        let num_samples = buffer.samples();
+        let sync = self.params.sync.value();
+        self.clock
+            .set_rate(self.params.rate.value(), context.transport().sample_rate);
+
+        let mut timing = match sync {
+            SyncMode::Host => Timing::from_transport(context.transport()),
+            SyncMode::Free | SyncMode::Note => Some(self.clock.timing()),
+        };
+
+        // Controllers that were just unmapped let go of their scratch.
+        let scratch_control = self.params.scratch_control.load(Ordering::Relaxed);
+        if ScratchControl::from_raw(scratch_control) == ScratchControl::Off {
+            self.beat_engine.scratch(None);
+        }
+
+        self.beat_engine
+            .set_crossfade(self.params.beat_crossfade.value());
+        self.beat_engine
+            .set_resampling(self.params.resampling.value());
+        self.vol_engine.set_envelope(
+            self.params.vol_attack.value(),
+            self.params.vol_release.value(),
+        );
+
+        for (dry, channel) in self.dry.iter_mut().zip(buffer.as_slice_immutable()) {
+            if let Some(dry) = dry.get_mut(..num_samples) {
+                dry.copy_from_slice(channel);
+            }
+        }
+
+        let tables = self.tables.read();
+
+        // Notes can switch presets and restart the curves, and controllers can
+        // scratch, so the block is processed in pieces between events.
+        let mut start = 0;
+        loop {
+            let event = context.next_event();
+            let end = event.as_ref().map_or(num_samples, |event| {
+                (event.timing() as usize).clamp(start, num_samples)
+            });
+
+            let beat_timing = self.beat_trigger.timing(timing);
+            let beat = tables.beat(self.beat_trigger.slot(self.params.beat.value() as usize));
+            let beat_mix = &self.params.beat_mix.smoothed;
+            self.beat_engine
+                .process(buffer, start..end, beat_timing, beat, beat_mix);
+
+            // The volume is applied on top of the remapped audio.
+            let vol_timing = self.vol_trigger.timing(timing);
+            let vol = tables.vol(self.vol_trigger.slot(self.params.vol.value() as usize));
+            let vol_mix = &self.params.vol_mix.smoothed;
+            self.vol_engine
+                .process(buffer, start..end, vol_timing, vol, vol_mix);
+
+            start = end;
+            let Some(event) = event else {
+                let beat_length = self.beat_engine.loop_length(beat_timing, beat);
+                let vol_length = vol.map_or(Length::default(), CurveTable::length);
+                self.playheads
+                    .update(start, (beat_timing, beat_length), (vol_timing, vol_length));
+                break;
+            };
+
+            match event {
+                NoteEvent::NoteOn { note, .. } => {
+                    if sync == SyncMode::Note {
+                        timing = Some(self.clock.restart(start));
+                    }
+
+                    let beats_per_sample = timing.map_or(0.0, |timing| timing.beats_per_sample);
+                    match trigger::note_slot(note) {
+                        Some((Mode::Beat, slot)) => {
+                            self.beat_trigger.press(note, slot, start, beats_per_sample)
+                        }
+                        Some((Mode::Vol, slot)) => {
+                            self.vol_trigger.press(note, slot, start, beats_per_sample)
+                        }
+                        None => {}
+                    }
+                }
+                NoteEvent::NoteOff { note, .. } => {
+                    self.beat_trigger.release(note);
+                    self.vol_trigger.release(note);
+                }
+                _ => {
+                    if let Some((control, offset)) = ScratchControl::from_event(&event) {
+                        if self.params.scratch_learn.swap(false, Ordering::Relaxed) {
+                            self.params
+                                .scratch_control
+                                .store(control.to_raw(), Ordering::Relaxed);
+                            self.beat_engine.scratch(None);
+                        }
+
+                        let scratch_control = self.params.scratch_control.load(Ordering::Relaxed);
+                        if control == ScratchControl::from_raw(scratch_control) {
+                            self.beat_engine.scratch(offset);
+                        }
+                    }
+                }
+            }
+        }
+
+        let beats_per_sample = timing.map_or(0.0, |timing| timing.beats_per_sample);
+        self.beat_trigger.advance(num_samples, beats_per_sample);
+        self.vol_trigger.advance(num_samples, beats_per_sample);
+        if sync != SyncMode::Host {
+            self.clock.advance(num_samples);
+        }
+
+        // The engines keep running while bypassed, so that they can be faded
+        // back in without a jump.
+        let sample_rate = context.transport().sample_rate;
+        let active = if self.params.bypass.value() { 0.0 } else { 1.0 };
+        self.active.set_target(sample_rate, active);
+
+        for (sample_idx, mut frame) in buffer.iter_samples().enumerate() {
+            let gain = self.params.output_gain.smoothed.next();
+            let active = self.active.next();
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                let dry = self.dry.get(channel).map_or(0.0, |dry| dry[sample_idx]);
+                *sample = dry + (*sample * gain - dry) * active;
+            }
+        }
+
         ProcessStatus::Normal
     }
 }
 
+/// Samples both preset banks and hands the tables over to the audio thread.
+fn rebuild_tables(params: &FunctorParams, tables_input: &Mutex<triple_buffer::Input<Tables>>) {
+    let tables = Tables::new(
+        &params.beat_presets.read().unwrap(),
+        &params.vol_presets.read().unwrap(),
+    );
+    tables_input.lock().unwrap().write(tables);
+}
+
 impl Vst3Plugin for Functor {
     const VST3_CLASS_ID: [u8; 16] = *b"fooofooofooofooo";
     const VST3_SUBCATEGORIES: &'static [Vst3SubCategory] =
@@ -0,0 +1,138 @@
+//! The user's preset library.
+//!
+//! Preset files dropped anywhere under [`library_dir`] are picked up in the
+//! background when the plugin starts and whenever the editor opens. The first
+//! folder a preset is in is its category, and any folders below that are its
+//! tags, so `Gates/Trance/Offbeat.json` is in the `Gates` category with the
+//! `Trance` tag. Presets right in the library folder have no category.
+
+use nih_plug::nih_log;
+use nih_plug_vizia::vizia::prelude::Data;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::RwLock;
+
+use crate::curve::{Mode, ModePreset};
+use crate::file;
+
+/// The library folder, inside the user's data folder. On Linux, this is
+/// `$XDG_DATA_HOME/functor/presets`.
+pub fn library_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("functor").join("presets"))
+}
+
+/// A preset found in the library.
+#[derive(Clone, Data)]
+pub struct LibraryPreset {
+    /// The preset and the mode it's meant for.
+    pub preset: ModePreset,
+
+    /// The folder the preset is in, right under the library folder, if any.
+    pub category: Option<String>,
+
+    /// The folders below the category that the preset is in.
+    pub tags: Vec<String>,
+}
+
+impl LibraryPreset {
+    /// Whether the name or any tag of the preset contains a search string,
+    /// ignoring case. Every preset matches an empty search.
+    pub fn matches(&self, search: &str) -> bool {
+        let search = search.trim().to_lowercase();
+        search.is_empty()
+            || self.preset.preset.name.to_lowercase().contains(&search)
+            || self
+                .tags
+                .iter()
+                .any(|tag| tag.to_lowercase().contains(&search))
+    }
+}
+
+/// The presets in the library as of the last scan, shared between the
+/// background thread that scans for them and the editor.
+#[derive(Default)]
+pub struct Library {
+    /// The presets found by the last scan.
+    presets: RwLock<Vec<LibraryPreset>>,
+
+    /// How many scans have finished, so that the editor can tell when to pick
+    /// up the presets again.
+    generation: AtomicU32,
+}
+
+impl Library {
+    /// Scans the library folder and replaces the presets by what's found. This
+    /// touches the file system, so it's meant for a background thread.
+    pub fn rescan(&self) {
+        let presets = scan();
+        *self.presets.write().unwrap() = presets;
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+
+    /// The number of scans that have finished.
+    pub fn generation(&self) -> u32 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// The presets found by the last scan.
+    pub fn presets(&self) -> Vec<LibraryPreset> {
+        self.presets.read().unwrap().clone()
+    }
+}
+
+/// Loads every preset in the library folder, sorted by category and name.
+/// Files that can't be loaded are skipped.
+fn scan() -> Vec<LibraryPreset> {
+    let mut presets = Vec::new();
+    if let Some(dir) = library_dir() {
+        scan_dir(&dir, &mut Vec::new(), &mut presets);
+    }
+
+    presets.sort_by(|a, b| {
+        (&a.category, &a.preset.preset.name).cmp(&(&b.category, &b.preset.preset.name))
+    });
+    presets
+}
+
+/// Loads every preset in a folder and its subfolders, where `folders` are the
+/// folders between the library folder and this one. Links to folders aren't
+/// followed, so that a link to a folder above can't send this in circles.
+fn scan_dir(dir: &Path, folders: &mut Vec<String>, presets: &mut Vec<LibraryPreset>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let is_dir = entry
+            .file_type()
+            .map_or(false, |file_type| file_type.is_dir());
+        if is_dir {
+            folders.push(entry.file_name().to_string_lossy().into_owned());
+            scan_dir(&path, folders, presets);
+            folders.pop();
+        } else if path.extension().map_or(false, |ext| ext == file::EXTENSION) {
+            match file::load_preset(&path) {
+                Ok(preset) => presets.push(LibraryPreset {
+                    preset,
+                    category: folders.first().cloned(),
+                    tags: folders.iter().skip(1).cloned().collect(),
+                }),
+                Err(err) => nih_log!("Skipping {}: {err}", path.display()),
+            }
+        }
+    }
+}
+
+/// The categories that hold presets for a given mode, in order. `None` stands
+/// for the presets that aren't in any category.
+pub fn categories(presets: &[LibraryPreset], mode: Mode) -> Vec<Option<String>> {
+    let mut categories: Vec<Option<String>> = presets
+        .iter()
+        .filter(|preset| preset.preset.mode == mode)
+        .map(|preset| preset.category.clone())
+        .collect();
+    categories.dedup();
+    categories
+}
@@ -0,0 +1,211 @@
+//! Upgrading presets saved by older versions of Functor.
+//!
+//! Every serialized [`Preset`][crate::curve::Preset] records the version of the
+//! schema it was written with. On load, older presets are upgraded one version
+//! at a time before being read, so that projects and files keep loading as the
+//! format grows. Presets from before versioning was introduced count as
+//! version 1.
+
+use serde_json::{json, Map, Value};
+use std::fmt;
+
+/// The version of the preset schema that's written.
+pub const PRESET_VERSION: u64 = 3;
+
+/// The key the schema version is stored under.
+pub const VERSION_KEY: &str = "version";
+
+/// The upgrades between consecutive versions. The upgrade at index `i` turns a
+/// version `i + 1` preset into a version `i + 2` one.
+const UPGRADES: [fn(&mut Map<String, Value>); PRESET_VERSION as usize - 1] = [from_v1, from_v2];
+
+/// A reason why a preset couldn't be upgraded.
+#[derive(Debug)]
+pub enum MigrateError {
+    /// The preset isn't a map.
+    NotAnObject,
+
+    /// The version isn't a positive integer.
+    InvalidVersion,
+
+    /// The preset was written by a newer version of Functor.
+    UnsupportedVersion(u64),
+}
+
+impl fmt::Display for MigrateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotAnObject => write!(f, "a preset must be a map"),
+            Self::InvalidVersion => write!(f, "the preset version must be a positive integer"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported preset version {version}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MigrateError {}
+
+/// Upgrades a serialized preset to the current version.
+pub fn migrate_preset(value: &mut Value) -> Result<(), MigrateError> {
+    let preset = value.as_object_mut().ok_or(MigrateError::NotAnObject)?;
+    let version = match preset.get(VERSION_KEY) {
+        None => 1,
+        Some(version) => version
+            .as_u64()
+            .filter(|&version| version > 0)
+            .ok_or(MigrateError::InvalidVersion)?,
+    };
+
+    if version > PRESET_VERSION {
+        return Err(MigrateError::UnsupportedVersion(version));
+    }
+
+    for upgrade in &UPGRADES[version as usize - 1..] {
+        upgrade(preset);
+    }
+
+    preset.insert(VERSION_KEY.to_owned(), json!(PRESET_VERSION));
+    Ok(())
+}
+
+/// Version 1 had neither tension nor interpolation modes, and its curves were
+/// drawn with straight lines.
+fn from_v1(preset: &mut Map<String, Value>) {
+    let Some(Value::Object(curve)) = preset.get_mut("curve") else {
+        return;
+    };
+
+    curve
+        .entry("interpolation")
+        .or_insert_with(|| json!("Linear"));
+
+    if let Some(Value::Array(nodes)) = curve.get_mut("nodes") {
+        for node in nodes {
+            if let Value::Object(node) = node {
+                node.entry("tension").or_insert_with(|| json!(0.0));
+            }
+        }
+    }
+}
+
+/// Version 2 had no lengths, and every curve lasted a bar.
+fn from_v2(preset: &mut Map<String, Value>) {
+    preset
+        .entry("length")
+        .or_insert_with(|| json!({ "count": 1, "unit": "Bars" }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::Preset;
+
+    /// A preset from before versions were recorded, with straight lines and no
+    /// tension.
+    const V1: &str = r#"{
+        "name": "Half time",
+        "curve": { "nodes": [{ "x": 0.0, "y": 0.0 }, { "x": 1.0, "y": 0.5 }] }
+    }"#;
+
+    /// A preset from before lengths, with its own interpolation and tensions.
+    const V2: &str = r#"{
+        "version": 2,
+        "name": "Swing",
+        "curve": {
+            "nodes": [
+                { "x": 0.0, "y": 0.0, "tension": 0.5 },
+                { "x": 1.0, "y": 1.0, "tension": 0.0 }
+            ],
+            "interpolation": "Cubic"
+        }
+    }"#;
+
+    /// A preset in the current version, which only lasts two beats.
+    const V3: &str = r#"{
+        "version": 3,
+        "name": "Gate",
+        "curve": {
+            "nodes": [
+                { "x": 0.0, "y": 1.0, "tension": 0.0 },
+                { "x": 0.5, "y": 0.0, "tension": 0.0 },
+                { "x": 1.0, "y": 0.0, "tension": 0.0 }
+            ],
+            "interpolation": "Drop"
+        },
+        "length": { "count": 2, "unit": "Beats" }
+    }"#;
+
+    /// Loads a preset and saves it again.
+    fn round_trip(text: &str) -> Value {
+        let preset: Preset = serde_json::from_str(text).expect("the preset should load");
+        serde_json::to_value(&preset).expect("the preset should save")
+    }
+
+    #[test]
+    fn v1_gets_lines_and_a_bar() {
+        assert_eq!(
+            round_trip(V1),
+            json!({
+                "version": PRESET_VERSION,
+                "name": "Half time",
+                "curve": {
+                    "nodes": [
+                        { "x": 0.0, "y": 0.0, "tension": 0.0 },
+                        { "x": 1.0, "y": 0.5, "tension": 0.0 }
+                    ],
+                    "interpolation": "Linear"
+                },
+                "length": { "count": 1, "unit": "Bars" }
+            })
+        );
+    }
+
+    #[test]
+    fn v2_keeps_its_curve_and_gets_a_bar() {
+        assert_eq!(
+            round_trip(V2),
+            json!({
+                "version": PRESET_VERSION,
+                "name": "Swing",
+                "curve": {
+                    "nodes": [
+                        { "x": 0.0, "y": 0.0, "tension": 0.5 },
+                        { "x": 1.0, "y": 1.0, "tension": 0.0 }
+                    ],
+                    "interpolation": "Cubic"
+                },
+                "length": { "count": 1, "unit": "Bars" }
+            })
+        );
+    }
+
+    #[test]
+    fn v3_is_unchanged() {
+        let mut expected: Value = serde_json::from_str(V3).unwrap();
+        expected[VERSION_KEY] = json!(PRESET_VERSION);
+        assert_eq!(round_trip(V3), expected);
+    }
+
+    #[test]
+    fn newer_and_invalid_versions_are_rejected() {
+        let mut newer = json!({ "version": PRESET_VERSION + 1 });
+        assert!(matches!(
+            migrate_preset(&mut newer),
+            Err(MigrateError::UnsupportedVersion(version)) if version == PRESET_VERSION + 1
+        ));
+
+        for version in [json!(0), json!(-1), json!(1.5), json!("3")] {
+            let mut invalid = json!({ "version": version });
+            assert!(matches!(
+                migrate_preset(&mut invalid),
+                Err(MigrateError::InvalidVersion)
+            ));
+        }
+
+        assert!(matches!(
+            migrate_preset(&mut json!([])),
+            Err(MigrateError::NotAnObject)
+        ));
+    }
+}